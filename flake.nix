@@ -60,6 +60,7 @@
             pkgs.bun
             pkgs.rojo
             pkgs.hyperfine
+            pkgs.zstd # compresses the zstd decompressor's test frames
 
             wally
             run-in-cloud-pkg # a run-in-roblox replacement
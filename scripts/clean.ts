@@ -6,6 +6,20 @@ for await (const file of new Glob("encoding/testRbxms/*.{luau,bin}").scan(
 	await Bun.file(file).delete();
 }
 
+for await (const file of new Glob("encoding/testDecoders/*.luau").scan(".")) {
+	await Bun.file(file).delete();
+}
+
+for await (const file of new Glob("encoding/testZstd/*.{luau,zst,dict}").scan(
+	".",
+)) {
+	await Bun.file(file).delete();
+}
+
 for await (const file of new Glob("examples/*.{luau,bin,zst}").scan(".")) {
 	await Bun.file(file).delete();
 }
+
+for await (const file of new Glob("examples/columnar/*.{bin,zst}").scan(".")) {
+	await Bun.file(file).delete();
+}
@@ -1,4 +1,5 @@
 import { $, Glob } from "bun";
+import { mkdir } from "node:fs/promises";
 import { ZstdInit, ZstdStream } from "@oneidentity/zstd-js";
 import chalk from "chalk";
 
@@ -10,10 +11,12 @@ const platformBinary =
 		: "./target/debug/azalea";
 
 await $`cargo build`;
+await mkdir("examples/columnar", { recursive: true });
 
 await Promise.all([
 	ZstdInit(),
 	$`${platformBinary} encode --input examples/*.rbxm --output examples`,
+	$`${platformBinary} encode --input examples/*.rbxm --output examples/columnar --columnar --shuffle`,
 	$`${platformBinary} generate-embeddable-script --input examples/*.rbxm --output examples`,
 ]);
 
@@ -45,14 +48,16 @@ const promises = [];
 for await (const file of glob.scan()) {
 	// const luauFilePath = file.replace(fileExtensionRegex, ".luau");
 	const binFilePath = file.replace(fileExtensionRegex, ".bin");
-	const binZstFilePath = binFilePath + ".zst";
+	const columnarBinFilePath = binFilePath.replace("examples", "examples/columnar");
 
-	promises.push(
-		Bun.write(
-			Bun.file(binZstFilePath),
-			ZstdStream.compress(await Bun.file(binFilePath).bytes(), 22, true),
-		),
-	);
+	for (const path of [binFilePath, columnarBinFilePath]) {
+		promises.push(
+			Bun.write(
+				Bun.file(path + ".zst"),
+				ZstdStream.compress(await Bun.file(path).bytes(), 22, true),
+			),
+		);
+	}
 }
 
 await Promise.all(promises);
@@ -83,3 +88,22 @@ for await (const rbxmFilePath of glob.scan()) {
 		);
 	}
 }
+
+console.log();
+
+for await (const rbxmFilePath of glob.scan()) {
+	const binZstFilePath = rbxmFilePath.replace(fileExtensionRegex, ".bin.zst");
+	const columnarBinZstFilePath = binZstFilePath.replace("examples", "examples/columnar");
+
+	const rowSize = Bun.file(binZstFilePath).size;
+	const columnarSize = Bun.file(columnarBinZstFilePath).size;
+	const difference = columnarSize - rowSize;
+
+	console.log(
+		`${chalk.green(rbxmFilePath)}: columnar layout (--columnar --shuffle) is ${
+			difference > 0
+				? chalk.redBright(`larger by ${formatBytes(difference)}`)
+				: chalk.cyan(`smaller by ${formatBytes(-difference)}`)
+		} than row layout (${formatBytes(columnarSize)} vs ${formatBytes(rowSize)})`,
+	);
+}
@@ -1,5 +1,6 @@
 import { Glob, $ } from "bun";
 import { ZstdInit, ZstdStream } from "@oneidentity/zstd-js";
+import { mkdir } from "node:fs/promises";
 
 const platformBinary =
 	process.platform === "win32"
@@ -7,9 +8,12 @@ const platformBinary =
 		: "./target/debug/azalea";
 
 await $`cargo build`;
+await mkdir("encoding/testDecoders", { recursive: true });
 await Promise.all([
 	ZstdInit(),
 	$`${platformBinary} generate-full-decoder encoding/decoder.luau --format`,
+	$`${platformBinary} generate-full-decoder encoding/testDecoders/yielding.luau --format --yielding`,
+	$`${platformBinary} generate-full-decoder encoding/testDecoders/propertyErrorReport.luau --format --property-error-report`,
 	$`${platformBinary} encode --input encoding/testRbxms/*.rbxm --output encoding/testRbxms`,
 ]);
 
@@ -28,3 +32,28 @@ for await (const file of glob.scan(".")) {
 		`return game:GetService("HttpService"):JSONDecode([[{"m":null,"t":"buffer","zbase64":"${encodedBytes}"}]]) :: buffer`,
 	);
 }
+
+// zstd frames (compressed by the reference implementation) for the pure-Luau zstd decompressor's tests
+const bufferModule = (bytes: Uint8Array) =>
+	`return game:GetService("HttpService"):JSONDecode([[{"m":null,"t":"buffer","base64":"${Buffer.from(bytes).toString("base64")}"}]]) :: buffer`;
+
+await Bun.write(
+	"encoding/testZstd/zstd.luau",
+	`${await Bun.file("src/luau/zstd.luau").text()}\nreturn { decompressZstd = decompressZstd, loadZstdDictionary = loadZstdDictionary }\n`,
+);
+await $`${platformBinary} train-dictionary --input encoding/testRbxms/*.bin --output encoding/testZstd/trainedDictionary.dict --size 4096`;
+await Promise.all([
+	$`zstd -q -f --ultra -1 encoding/testRbxms/AndroidRobotMeshPart.bin -o encoding/testZstd/level1.zst`,
+	$`zstd -q -f --ultra -19 encoding/testRbxms/AndroidRobotMeshPart.bin -o encoding/testZstd/level19.zst`,
+	$`zstd -q -f --ultra -22 encoding/testRbxms/AndroidRobotMeshPart.bin -o encoding/testZstd/level22.zst`,
+	// a raw content dictionary is any file without the dictionary magic, which frames can only reference matches from
+	$`zstd -q -f -19 -D encoding/testRbxms/allPossibleFacesValues.bin encoding/testRbxms/allPossibleAxesValues.bin -o encoding/testZstd/withRawDictionary.zst`,
+	$`zstd -q -f -19 -D encoding/testZstd/trainedDictionary.dict encoding/testRbxms/exampleCustomPhysicalProperties.bin -o encoding/testZstd/withTrainedDictionary.zst`,
+]);
+
+for await (const file of new Glob("encoding/testZstd/*.{zst,dict}").scan(".")) {
+	await Bun.write(
+		file.replace(fileExtensionRegex, ".luau"),
+		bufferModule(await Bun.file(file).bytes()),
+	);
+}
@@ -1,5 +1,6 @@
 //! License compliant port of <https://github.com/vence722/base122-go/blob/main/encoder.go>.
-//! A writer once lived here, but it was not implemented correctly.
+//! [`Base122Writer`] streams the same output as [`Base122OriginalEncoder`], for when the data isn't all in memory,
+//! and [`decode_with_illegal_bytes`] reverses both (like `src/luau/base122.luau` does in scripts).
 
 use std::io::Write;
 
@@ -21,14 +22,65 @@ pub const BASE123_ILLEGAL_BYTES: [u8; 5] = [0, 10, 13, 34, 92];
 /// See <https://github.com/kevinAlbs/Base122/tree/master> for more details.
 pub const BASE122_ILLEGAL_BYTES: [u8; 6] = [0, 10, 13, 34, 38, 92];
 
+/// Base123 for a Lua single quoted string, so the single quote (39) is illegal instead of the double quote.
+pub const SINGLE_QUOTED_ILLEGAL_BYTES: [u8; 5] = [0, 10, 13, 39, 92];
+
+/// For a Lua long bracket string (`[[...]]`), which only ends at `]]` and has no escapes.
+/// The closing bracket (93) is illegal, and so are newlines and carriage returns, as Lua normalizes line endings in them.
+/// The opening bracket (91) is illegal too, as Lua 5.1 rejects `[[` inside of `[[...]]` (see [`crate::dense::long_bracket_level`]).
+pub const LONG_BRACKET_ILLEGAL_BYTES: [u8; 5] = [0, 10, 13, 91, 93];
+
 const SHORTENED: u8 = 0b111;
 
-/// You will probably never encounter this enum when using the encoder.
+/// You will probably never encounter [`Error::EndOfStream`] when using the encoder, but the decoder uses the other
+/// variants to reject data which the encoder could not have produced. Offsets are in bytes, into the encoded data.
 #[derive(Eq, PartialEq, Debug)]
 pub enum Error {
 	EndOfStream,
+	/// A byte which doesn't start a one or two byte character the encoder produces, or a two byte character which is
+	/// cut off or doesn't continue properly.
+	MalformedSequence {
+		offset: usize,
+	},
+	/// A two byte character refers to an illegal byte which isn't in the illegal bytes.
+	InvalidIllegalByteIndex {
+		offset: usize,
+		index: u8,
+	},
+	/// An illegal byte which wasn't encoded into a two byte character.
+	UnencodedIllegalByte {
+		offset: usize,
+		byte: u8,
+	},
+	/// A shortened two byte character (which only the last character can be) appeared before the end,
+	/// or doesn't contain an illegal byte.
+	MisplacedShortened {
+		offset: usize,
+	},
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::EndOfStream => f.write_str("unexpected end of stream"),
+			Self::MalformedSequence { offset } => {
+				write!(f, "malformed character at offset {offset}")
+			}
+			Self::InvalidIllegalByteIndex { offset, index } => {
+				write!(f, "invalid illegal byte index {index} at offset {offset}")
+			}
+			Self::UnencodedIllegalByte { offset, byte } => {
+				write!(f, "unencoded illegal byte {byte} at offset {offset}")
+			}
+			Self::MisplacedShortened { offset } => {
+				write!(f, "invalid shortened character at offset {offset}")
+			}
+		}
+	}
 }
 
+impl std::error::Error for Error {}
+
 /// Fully conformant Base122 encoder. Can be customized to change behaviour / increase encoder efficency.
 pub struct Base122OriginalEncoder<'data, const N: usize> {
 	illegal_bytes: [u8; N],
@@ -161,6 +213,208 @@ impl<'data, const N: usize> Base122OriginalEncoder<'data, N> {
 	}
 }
 
+/// Streaming Base122 encoder, which produces the same output as [`Base122OriginalEncoder`] with the same illegal bytes,
+/// no matter how the data is split across writes.
+///
+/// Bits which don't fill a 7 bit group yet (and an illegal group waiting for the group after it) are carried across
+/// writes, so [`Base122Writer::finish`] must be called to write them. Dropping the writer loses them.
+pub struct Base122Writer<W: Write, const N: usize> {
+	writer: W,
+	illegal_bytes: [u8; N],
+
+	/// Bits not yet part of a 7 bit group, in the lowest `bit_count` bits.
+	bits: u16,
+	bit_count: u8,
+	/// Index (into the illegal bytes) of an illegal group, which is encoded together with the next group.
+	pending_illegal_byte_index: Option<u8>,
+}
+
+impl<W: Write, const N: usize> Base122Writer<W, N> {
+	pub const fn new(writer: W, illegal_bytes: [u8; N]) -> Self {
+		Base122Writer {
+			writer,
+			illegal_bytes,
+			bits: 0,
+			bit_count: 0,
+			pending_illegal_byte_index: None,
+		}
+	}
+
+	/// Encodes a 7 bit group into `output`, see [`Base122OriginalEncoder::encode_into`].
+	fn push_group(&mut self, group: u8, output: &mut Vec<u8>) {
+		if let Some(illegal_byte_index) = self.pending_illegal_byte_index.take() {
+			output.extend_from_slice(&Self::encode_pair(illegal_byte_index, group));
+			return;
+		}
+
+		match self
+			.illegal_bytes
+			.iter()
+			.position(|&illegal_byte| illegal_byte == group)
+		{
+			Some(illegal_byte_index) => {
+				self.pending_illegal_byte_index = Some(
+					illegal_byte_index
+						.try_into()
+						.expect("to truncate illegal byte index into u8"),
+				);
+			}
+			None => output.push(group),
+		}
+	}
+
+	/// Encodes an illegal group (by its index, or [`SHORTENED`]) and the group after it into two bytes.
+	const fn encode_pair(illegal_byte_index: u8, group: u8) -> [u8; 2] {
+		[
+			0b1100_0010 | ((0b0000_0111 & illegal_byte_index) << 2) | ((0b0100_0000 & group) >> 6),
+			0b1000_0000 | (0b0011_1111 & group),
+		]
+	}
+
+	/// Writes the last (zero padded) group, and an illegal group which has no group after it, then returns the inner writer.
+	pub fn finish(mut self) -> std::io::Result<W> {
+		let mut output = Vec::with_capacity(2);
+
+		if self.bit_count > 0 {
+			let group = ((self.bits << (7 - self.bit_count)) & 0b0111_1111) as u8;
+			self.bit_count = 0;
+			self.push_group(group, &mut output);
+		}
+
+		// the illegal group itself is stored in the second byte
+		if let Some(illegal_byte_index) = self.pending_illegal_byte_index.take() {
+			let illegal_byte = self.illegal_bytes[usize::from(illegal_byte_index)];
+			output.extend_from_slice(&Self::encode_pair(SHORTENED, illegal_byte));
+		}
+
+		self.writer.write_all(&output)?;
+		Ok(self.writer)
+	}
+}
+
+impl<W: Write, const N: usize> Write for Base122Writer<W, N> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		// 1.75 * buf.len(), as in Base122OriginalEncoder::encode
+		let mut output = Vec::with_capacity((7 * buf.len()) / 4 + 2);
+
+		for &byte in buf {
+			self.bits = (self.bits << 8) | u16::from(byte);
+			self.bit_count += 8;
+
+			while self.bit_count >= 7 {
+				self.bit_count -= 7;
+				let group = ((self.bits >> self.bit_count) & 0b0111_1111) as u8;
+				self.push_group(group, &mut output);
+			}
+
+			self.bits &= (1 << self.bit_count) - 1;
+		}
+
+		self.writer.write_all(&output)?;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.writer.flush()
+	}
+}
+
+/// The alphabets which generated scripts can embed their payload with, one for each kind of Lua string literal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alphabet {
+	/// [`BASE123_ILLEGAL_BYTES`] in a double quoted string.
+	#[default]
+	DoubleQuoted,
+	/// [`SINGLE_QUOTED_ILLEGAL_BYTES`] in a single quoted string.
+	SingleQuoted,
+	/// [`LONG_BRACKET_ILLEGAL_BYTES`] in a long bracket string.
+	LongBracket,
+}
+
+impl Alphabet {
+	pub const ALL: [Self; 3] = [Self::DoubleQuoted, Self::SingleQuoted, Self::LongBracket];
+
+	#[must_use]
+	pub const fn illegal_bytes(self) -> &'static [u8] {
+		match self {
+			Self::DoubleQuoted => &BASE123_ILLEGAL_BYTES,
+			Self::SingleQuoted => &SINGLE_QUOTED_ILLEGAL_BYTES,
+			Self::LongBracket => &LONG_BRACKET_ILLEGAL_BYTES,
+		}
+	}
+
+	/// The opening and closing delimiters of the string literal encoded data is written into.
+	#[must_use]
+	pub const fn delimiters(self) -> (&'static str, &'static str) {
+		match self {
+			Self::DoubleQuoted => ("\"", "\""),
+			Self::SingleQuoted => ("'", "'"),
+			Self::LongBracket => ("[[", "]]"),
+		}
+	}
+
+	/// Encodes everything `write` writes into `writer` (see [`Base122Writer`]), then returns `writer`.
+	pub fn encode_with<W: Write>(
+		self,
+		writer: W,
+		write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>,
+	) -> std::io::Result<W> {
+		match self {
+			Self::DoubleQuoted => {
+				let mut base122_writer = Base122Writer::new(writer, BASE123_ILLEGAL_BYTES);
+				write(&mut base122_writer)?;
+				base122_writer.finish()
+			}
+			Self::SingleQuoted => {
+				let mut base122_writer = Base122Writer::new(writer, SINGLE_QUOTED_ILLEGAL_BYTES);
+				write(&mut base122_writer)?;
+				base122_writer.finish()
+			}
+			Self::LongBracket => {
+				let mut base122_writer = Base122Writer::new(writer, LONG_BRACKET_ILLEGAL_BYTES);
+				write(&mut base122_writer)?;
+				base122_writer.finish()
+			}
+		}
+	}
+
+	/// See [`decode_with_illegal_bytes`].
+	pub fn decode(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+		match self {
+			Self::DoubleQuoted => decode_with_illegal_bytes(data, BASE123_ILLEGAL_BYTES),
+			Self::SingleQuoted => decode_with_illegal_bytes(data, SINGLE_QUOTED_ILLEGAL_BYTES),
+			Self::LongBracket => decode_with_illegal_bytes(data, LONG_BRACKET_ILLEGAL_BYTES),
+		}
+	}
+}
+
+/// Generates the Luau base decoder for data encoded with `illegal_bytes`: a function named `f`,
+/// which takes the contents of a string literal and returns a buffer.
+///
+/// # Panics
+/// Panics if there are more than 7 illegal bytes (as index 7 marks a shortened character), or an illegal byte isn't ASCII.
+#[must_use]
+pub fn generate_luau_decoder(illegal_bytes: &[u8]) -> String {
+	assert!(
+		illegal_bytes.len() < usize::from(SHORTENED),
+		"there can be at most 7 illegal bytes"
+	);
+	assert!(
+		illegal_bytes.iter().all(u8::is_ascii),
+		"illegal bytes must be ASCII"
+	);
+
+	format!(
+		"local a=table.freeze({{{}}}) {}",
+		illegal_bytes
+			.iter()
+			.map(u8::to_string)
+			.collect::<Vec<_>>()
+			.join(","),
+		include_str!("./luau/minifiedCombinator.luau")
+	)
+}
+
 /// A more concise version of [`Base122OriginalEncoder::encode`]. Uses Base122 illegal bytes.
 #[must_use]
 pub fn base122_encode(data: &[u8]) -> Vec<u8> {
@@ -178,6 +432,89 @@ pub fn base123_encode(data: &[u8]) -> Vec<u8> {
 	Base122OriginalEncoder::new(data, BASE123_ILLEGAL_BYTES).encode()
 }
 
+/// Decodes data produced by [`Base122OriginalEncoder`] (or [`Base122Writer`]) with the same illegal bytes.
+pub fn decode_with_illegal_bytes<const N: usize>(
+	data: &[u8],
+	illegal_bytes: [u8; N],
+) -> Result<Vec<u8>, Error> {
+	let mut output = Vec::with_capacity((data.len() * 7) / 8);
+	let mut current_byte: u32 = 0;
+	let mut current_bit: u32 = 0;
+
+	let mut push_7_bits = |byte: u8| {
+		let byte = u32::from(byte) << 1;
+		current_byte |= byte >> current_bit;
+		current_bit += 7;
+
+		if current_bit >= 8 {
+			output.push(current_byte as u8);
+			current_bit -= 8;
+			current_byte = (byte << (7 - current_bit)) & 0xFF;
+		}
+	};
+
+	let mut offset = 0;
+	while offset < data.len() {
+		let first_byte = data[offset];
+
+		if first_byte < 0x80 {
+			if illegal_bytes.contains(&first_byte) {
+				return Err(Error::UnencodedIllegalByte {
+					offset,
+					byte: first_byte,
+				});
+			}
+
+			push_7_bits(first_byte);
+			offset += 1;
+			continue;
+		}
+
+		// two byte characters are always 0b110iii1b 0b10bbbbbb (see Base122OriginalEncoder::encode_into)
+		let second_byte = match data.get(offset + 1) {
+			Some(&second_byte)
+				if first_byte & 0b1110_0010 == 0b1100_0010 && second_byte & 0b1100_0000 == 0b1000_0000 =>
+			{
+				second_byte
+			}
+			_ => return Err(Error::MalformedSequence { offset }),
+		};
+
+		let illegal_byte_index = (first_byte >> 2) & 0b111;
+		let second_seven_bits_byte = ((first_byte & 1) << 6) | (second_byte & 0b0011_1111);
+
+		if illegal_byte_index == SHORTENED {
+			// the shortened character holds the last (illegal) 7 bits itself
+			if offset + 2 != data.len() || !illegal_bytes.contains(&second_seven_bits_byte) {
+				return Err(Error::MisplacedShortened { offset });
+			}
+		} else {
+			push_7_bits(*illegal_bytes.get(usize::from(illegal_byte_index)).ok_or(
+				Error::InvalidIllegalByteIndex {
+					offset,
+					index: illegal_byte_index,
+				},
+			)?);
+		}
+
+		push_7_bits(second_seven_bits_byte);
+		offset += 2;
+	}
+
+	Ok(output)
+}
+
+/// A more concise version of [`decode_with_illegal_bytes`]. Uses Base122 illegal bytes.
+pub fn base122_decode(data: &[u8]) -> Result<Vec<u8>, Error> {
+	decode_with_illegal_bytes(data, BASE122_ILLEGAL_BYTES)
+}
+
+/// A more concise version of [`decode_with_illegal_bytes`]. Uses Base123 illegal bytes.
+pub fn base123_decode(data: &[u8]) -> Result<Vec<u8>, Error> {
+	decode_with_illegal_bytes(data, BASE123_ILLEGAL_BYTES)
+}
+
+#[cfg(test)]
 mod tests {
 	use super::*;
 
@@ -211,10 +548,207 @@ mod tests {
 		};
 	}
 
+	/// Deterministic xorshift generator, so failures are reproducible.
+	struct Random(u64);
+
+	impl Random {
+		fn next(&mut self) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0
+		}
+
+		fn below(&mut self, bound: usize) -> usize {
+			(self.next() % bound as u64) as usize
+		}
+	}
+
+	/// Encodes `data` with a [`Base122Writer`], split into random chunks.
+	fn write_in_chunks<const N: usize>(
+		data: &[u8],
+		illegal_bytes: [u8; N],
+		random: &mut Random,
+	) -> Vec<u8> {
+		let mut writer = Base122Writer::new(Vec::new(), illegal_bytes);
+		let mut remaining = data;
+
+		while !remaining.is_empty() {
+			let (chunk, rest) = remaining.split_at(random.below(remaining.len() + 1));
+			writer.write_all(chunk).unwrap();
+			remaining = rest;
+		}
+
+		writer.finish().unwrap()
+	}
+
+	#[test]
+	fn writer_matches_original_encoder() {
+		let mut random = Random(0x9E37_79B9_7F4A_7C15);
+
+		for length in 0..300 {
+			let data = random_data(length, &mut random);
+
+			for _ in 0..8 {
+				assert_eq!(
+					write_in_chunks(&data, BASE123_ILLEGAL_BYTES, &mut random),
+					base123_encode(&data),
+					"{data:?}"
+				);
+				assert_eq!(
+					write_in_chunks(&data, BASE122_ILLEGAL_BYTES, &mut random),
+					base122_encode(&data),
+					"{data:?}"
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn writer_handles_shortened_last_character() {
+		for length in 0..32 {
+			let data = vec![0; length];
+			let mut writer = Base122Writer::new(Vec::new(), BASE123_ILLEGAL_BYTES);
+			for byte in &data {
+				writer.write_all(std::slice::from_ref(byte)).unwrap();
+			}
+
+			assert_eq!(writer.finish().unwrap(), base123_encode(&data));
+		}
+	}
+
+	/// Random data, which is mostly illegal bytes half of the time, so illegal groups (and the shortened last
+	/// character) are common.
+	fn random_data(length: usize, random: &mut Random) -> Vec<u8> {
+		let illegal_heavy = random.below(2) == 0;
+
+		(0..length)
+			.map(|_| {
+				if illegal_heavy {
+					BASE122_ILLEGAL_BYTES[random.below(BASE122_ILLEGAL_BYTES.len())] << random.below(2)
+				} else {
+					random.next() as u8
+				}
+			})
+			.collect()
+	}
+
+	#[test]
+	fn decode_round_trips() {
+		let mut random = Random(0x2545_F491_4F6C_DD1D);
+
+		for length in 0..1000 {
+			let data = random_data(length, &mut random);
+
+			assert_eq!(base123_decode(&base123_encode(&data)), Ok(data.clone()));
+			assert_eq!(base122_decode(&base122_encode(&data)), Ok(data.clone()));
+			assert_eq!(
+				decode_with_illegal_bytes(
+					&Base122OriginalEncoder::new(&data, [0, 93]).encode(),
+					[0, 93]
+				),
+				Ok(data)
+			);
+		}
+	}
+
+	#[test]
+	fn long_bracket_data_never_nests() {
+		let mut random = Random(0x94D0_49BB_1331_11EB);
+
+		for length in 0..300 {
+			let mut data = random_data(length, &mut random);
+			data.extend_from_slice(b"[[]]");
+
+			let encoded = Alphabet::LongBracket
+				.encode_with(Vec::new(), |writer| writer.write_all(&data))
+				.unwrap();
+			assert!(!encoded.contains(&b']'), "{data:?}");
+			assert!(
+				!encoded.windows(2).any(|window| window == b"[["),
+				"{data:?}"
+			);
+			assert_eq!(Alphabet::LongBracket.decode(&encoded), Ok(data));
+		}
+	}
+
+	#[test]
+	fn decode_never_panics_on_garbage() {
+		let mut random = Random(0xD1B5_4A32_D192_ED03);
+
+		for length in 0..1000 {
+			let mut encoded = base123_encode(&random_data(length, &mut random));
+			if let Some(byte) = encoded.get_mut(random.below(length + 1)) {
+				*byte = random.next() as u8;
+			}
+
+			// the result doesn't matter, as long as it doesn't panic
+			let _ = base123_decode(&encoded);
+		}
+	}
+
+	#[test]
+	fn decode_rejects_malformed_data() {
+		// cut off two byte character
+		assert_eq!(
+			base123_decode(&[65, 194]),
+			Err(Error::MalformedSequence { offset: 1 })
+		);
+		// second byte isn't a continuation byte
+		assert_eq!(
+			base123_decode(&[194, 65]),
+			Err(Error::MalformedSequence { offset: 0 })
+		);
+		// three byte characters, stray continuation bytes and overlong characters are never produced
+		assert_eq!(
+			base123_decode(&[0xE2, 0x82, 0xAC]),
+			Err(Error::MalformedSequence { offset: 0 })
+		);
+		assert_eq!(
+			base123_decode(&[65, 0x80]),
+			Err(Error::MalformedSequence { offset: 1 })
+		);
+		assert_eq!(
+			base123_decode(&[0xC0, 0x80]),
+			Err(Error::MalformedSequence { offset: 0 })
+		);
+
+		// Base123 has 5 illegal bytes, so index 5 (0b11010110) is out of bounds; Base122's is the backslash
+		assert_eq!(
+			base123_decode(&[0b1101_0110, 0b1000_0000]),
+			Err(Error::InvalidIllegalByteIndex {
+				offset: 0,
+				index: 5
+			})
+		);
+		assert_eq!(
+			base122_decode(&[0b1101_0110, 0b1000_0000]),
+			Ok(vec![b'\\' << 1])
+		);
+
+		assert_eq!(
+			base123_decode(b"ab\"c"),
+			Err(Error::UnencodedIllegalByte {
+				offset: 2,
+				byte: b'"'
+			})
+		);
+
+		// a shortened character must be last, and hold an illegal byte
+		assert_eq!(
+			base123_decode(&[222, 128, 65]),
+			Err(Error::MisplacedShortened { offset: 0 })
+		);
+		assert_eq!(
+			base123_decode(&[222, 129]),
+			Err(Error::MisplacedShortened { offset: 0 })
+		);
+	}
+
 	mod base123 {
 		use super::*;
 
-		generate_base123_test!(&[], &[], empty_vectors_alike);
+		generate_base123_test!(&[], &[] as &[u8], empty_vectors_alike);
 
 		generate_base123_test!(
 			b"hello world",
@@ -0,0 +1,1737 @@
+//! Azalea's decoding logic
+//!
+//! Decoding is the inverse of [`crate::encoder::encode_dom_into_writer`], and is only as lossless as encoding is:
+//! - `Int64` values which fit inside of an `i32` are decoded as `Int32`, and the rest are decoded as `Float64`
+//! - `ContentId` and `UniqueId` values are decoded as `String`
+//! - `EnumItem` values are decoded as `Enum`
+//! - `SharedString` and `NetAssetRef` values are decoded as `BinaryString`
+//! - the acoustic absorption of `PhysicalProperties` is not encoded, and is decoded as Roblox's default of 1.0
+//! - properties encoded as [`TypeId::None`] (nil referents, empty `OptionalCFrame`s and inlined sources) are omitted
+
+use crate::spec::{
+	ColumnKind, FORMAT_MAGIC, FORMAT_VERSION, FormatFlags, PATCH_MAGIC, PatchRecordKind, RecordKind,
+	TypeId,
+};
+use color_eyre::eyre::{self, WrapErr, bail, ensure, eyre};
+use rbx_dom_weak::{
+	InstanceBuilder, WeakDom,
+	types::{
+		Attributes, Axes, BinaryString, BrickColor, CFrame, Color3, Color3uint8, ColorSequence,
+		ColorSequenceKeypoint, Content, CustomPhysicalProperties, Enum, Faces, Font, FontStyle,
+		FontWeight, MaterialColors, Matrix3, NumberRange, NumberSequence, NumberSequenceKeypoint,
+		PhysicalProperties, Ray, Rect, Ref, Region3, Region3int16, SecurityCapabilities, Tags, UDim,
+		UDim2, Variant, Vector2, Vector2int16, Vector3, Vector3int16,
+	},
+};
+use std::{
+	collections::HashMap,
+	io::{BufRead, Read},
+};
+
+/// A decoded value which may need the entire tree to be decoded before it can become a [`Variant`].
+#[derive(Clone)]
+enum DecodedValue {
+	Variant(Variant),
+	/// Payload referent of a [`TypeId::Ref`].
+	Ref(u64),
+	/// Payload referent of a [`TypeId::ContentObject`].
+	ContentObject(u64),
+	/// [`TypeId::None`], the property is left unset.
+	None,
+}
+
+/// An instance as it is laid out in the payload, before it is inserted into a [`WeakDom`].
+struct DecodedInstance {
+	name: String,
+	class: String,
+	referent: u64,
+	parent: Option<u64>,
+	properties: Vec<(String, DecodedValue)>,
+}
+
+/// A [`RecordKind::Clone`] record, which copies the subtree of an earlier instance.
+struct DecodedClone {
+	name: String,
+	source: u64,
+	referent: u64,
+	parent: u64,
+	/// Referents of the cloned descendants, in encoding order.
+	descendants: Vec<u64>,
+}
+
+/// A record of a [`FormatFlags::COLUMNAR_LAYOUT`] payload, kept until every column is read.
+enum DecodedRecord {
+	Instance(DecodedInstance),
+	Clone(DecodedClone),
+}
+
+fn read_array<const N: usize>(reader: &mut impl BufRead) -> eyre::Result<[u8; N]> {
+	let mut bytes = [0; N];
+	reader
+		.read_exact(&mut bytes)
+		.wrap_err_with(|| format!("failed reading {N} bytes"))?;
+
+	Ok(bytes)
+}
+
+fn read_u8(reader: &mut impl BufRead) -> eyre::Result<u8> {
+	Ok(read_array::<1>(reader)?[0])
+}
+
+fn read_f32s<const N: usize>(reader: &mut impl BufRead) -> eyre::Result<[f32; N]> {
+	let mut floats = [0.0; N];
+	for float in &mut floats {
+		*float = f32::from_le_bytes(read_array(reader)?);
+	}
+
+	Ok(floats)
+}
+
+fn read_i16s<const N: usize>(reader: &mut impl BufRead) -> eyre::Result<[i16; N]> {
+	let mut ints = [0; N];
+	for int in &mut ints {
+		*int = i16::from_le_bytes(read_array(reader)?);
+	}
+
+	Ok(ints)
+}
+
+fn read_unsigned(reader: &mut impl BufRead) -> eyre::Result<u64> {
+	leb128::read::unsigned(reader).wrap_err("failed reading leb128 encoded unsigned integer")
+}
+
+fn read_length(reader: &mut impl BufRead) -> eyre::Result<usize> {
+	usize::try_from(read_unsigned(reader)?).wrap_err("failed truncating length to usize")
+}
+
+/// Reads `length` bytes, which are only allocated as they are read, as lengths of malformed payloads can't be trusted.
+fn read_bytes(reader: &mut impl BufRead, length: usize) -> eyre::Result<Vec<u8>> {
+	let mut bytes = Vec::new();
+	reader
+		.by_ref()
+		.take(u64::try_from(length)?)
+		.read_to_end(&mut bytes)?;
+	ensure!(
+		bytes.len() == length,
+		"expected {length} bytes, but the payload ends after {}",
+		bytes.len()
+	);
+
+	Ok(bytes)
+}
+
+/// NOTE: This function does not read any sort of type id.
+fn read_varstring(reader: &mut impl BufRead) -> eyre::Result<Vec<u8>> {
+	let length = read_length(reader).wrap_err("failed reading variable string length")?;
+	read_bytes(reader, length).wrap_err("failed reading variable string contents")
+}
+
+/// NOTE: This function does not read any sort of type id.
+fn read_nullstring(reader: &mut impl BufRead) -> eyre::Result<Vec<u8>> {
+	let mut string = Vec::new();
+	reader
+		.read_until(0, &mut string)
+		.wrap_err("failed reading nullstring")?;
+
+	ensure!(
+		string.pop() == Some(0),
+		"unexpected end of payload while reading nullstring"
+	);
+
+	Ok(string)
+}
+
+fn read_utf8_nullstring(reader: &mut impl BufRead) -> eyre::Result<String> {
+	String::from_utf8(read_nullstring(reader)?).wrap_err("nullstring is not valid utf-8")
+}
+
+fn read_utf8_varstring(reader: &mut impl BufRead) -> eyre::Result<String> {
+	String::from_utf8(read_varstring(reader)?).wrap_err("varstring is not valid utf-8")
+}
+
+/// The string table, and the blob table of [`FormatFlags::BLOB_TABLE`] payloads.
+struct Strings {
+	interned: Vec<String>,
+	blobs: Vec<Vec<u8>>,
+}
+
+/// NOTE: This function does not read any sort of type id.
+fn read_interned_string(reader: &mut impl BufRead, strings: &Strings) -> eyre::Result<String> {
+	let index = read_length(reader).wrap_err("failed reading interned string index")?;
+
+	strings
+		.interned
+		.get(index)
+		.cloned()
+		.ok_or_else(|| eyre!("interned string index {index} is out of bounds"))
+}
+
+/// Reads the string table which follows the header.
+fn read_string_table(reader: &mut impl BufRead) -> eyre::Result<Vec<String>> {
+	let length = read_length(reader).wrap_err("failed reading string table length")?;
+
+	(0..length)
+		.map(|_| read_utf8_varstring(reader).wrap_err("failed reading interned string"))
+		.collect()
+}
+
+/// Reads the blob table which follows the string table in [`FormatFlags::BLOB_TABLE`] payloads.
+fn read_blob_table(reader: &mut impl BufRead) -> eyre::Result<Vec<Vec<u8>>> {
+	let length = read_length(reader).wrap_err("failed reading blob table length")?;
+
+	(0..length)
+		.map(|_| read_varstring(reader).wrap_err("failed reading blob"))
+		.collect()
+}
+
+/// NOTE: This function does not read any sort of type id.
+fn read_blob<'strings>(
+	reader: &mut impl BufRead,
+	strings: &'strings Strings,
+) -> eyre::Result<&'strings [u8]> {
+	let index = read_length(reader).wrap_err("failed reading blob index")?;
+
+	strings
+		.blobs
+		.get(index)
+		.map(Vec::as_slice)
+		.ok_or_else(|| eyre!("blob index {index} is out of bounds"))
+}
+
+/// A ClassName and the property names its instances encode values for, see [`FormatFlags::PROPERTY_SCHEMAS`].
+struct Schema {
+	class: String,
+	property_names: Vec<String>,
+}
+
+/// Reads the schema table which follows the string table.
+fn read_schema_table(reader: &mut impl BufRead, strings: &Strings) -> eyre::Result<Vec<Schema>> {
+	let length = read_length(reader).wrap_err("failed reading schema table length")?;
+
+	(0..length)
+		.map(|_| {
+			let class = read_interned_string(reader, strings)
+				.wrap_err("failed reading interned string for schema ClassName")?;
+			let property_count = read_length(reader).wrap_err("failed reading schema property count")?;
+			let property_names = (0..property_count)
+				.map(|_| {
+					read_interned_string(reader, strings).wrap_err("failed reading schema property name")
+				})
+				.collect::<eyre::Result<_>>()?;
+
+			Ok(Schema {
+				class,
+				property_names,
+			})
+		})
+		.collect()
+}
+
+fn read_variant(reader: &mut impl BufRead, strings: &Strings) -> eyre::Result<DecodedValue> {
+	let type_id = read_u8(reader).wrap_err("failed reading type id")?;
+	let type_id = TypeId::try_from(type_id)?;
+
+	let variant = match type_id {
+		TypeId::String => Variant::String(read_utf8_varstring(reader)?),
+		TypeId::StringBlob => Variant::String(
+			String::from_utf8(read_blob(reader, strings)?.to_vec())
+				.wrap_err("string blob is not valid utf-8")?,
+		),
+		TypeId::BinaryStringBlob => Variant::BinaryString(read_blob(reader, strings)?.to_vec().into()),
+		TypeId::Attributes => {
+			let length = read_length(reader).wrap_err("failed reading attributes length")?;
+			let mut attributes = Attributes::new();
+
+			for _ in 0..length {
+				let name =
+					read_interned_string(reader, strings).wrap_err("failed reading attribute name")?;
+				let value = match read_variant(reader, strings)
+					.wrap_err_with(|| format!("failed reading attribute variant for {name}"))?
+				{
+					DecodedValue::Variant(variant) => variant,
+					_ => bail!("attribute {name} does not hold a plain value"),
+				};
+
+				attributes.insert(name, value);
+			}
+
+			Variant::Attributes(attributes)
+		}
+		TypeId::Axes => Variant::Axes(
+			Axes::from_bits(read_u8(reader)?).ok_or_else(|| eyre!("invalid bits for Axes"))?,
+		),
+		TypeId::Bool => Variant::Bool(read_u8(reader)? != 0),
+		TypeId::BrickColor => {
+			let name =
+				read_interned_string(reader, strings).wrap_err("failed reading name for BrickColor")?;
+			Variant::BrickColor(
+				BrickColor::from_name(&name).ok_or_else(|| eyre!("unknown BrickColor name {name}"))?,
+			)
+		}
+		TypeId::CFrame => {
+			let id = read_u8(reader).wrap_err("failed reading id for CFrame")?;
+
+			if id == 0 {
+				let [xx, xy, xz, yx, yy, yz, zx, zy, zz, x, y, z] = read_f32s(reader)?;
+				Variant::CFrame(CFrame::new(
+					Vector3::new(x, y, z),
+					Matrix3::new(
+						Vector3::new(xx, xy, xz),
+						Vector3::new(yx, yy, yz),
+						Vector3::new(zx, zy, zz),
+					),
+				))
+			} else {
+				let [x, y, z] = read_f32s(reader)?;
+				let orientation = Matrix3::from_basic_rotation_id(id)
+					.map_err(|_| eyre!("invalid basic rotation id {id:02x} for CFrame"))?;
+
+				Variant::CFrame(CFrame::new(Vector3::new(x, y, z), orientation))
+			}
+		}
+		TypeId::Color3 => {
+			let [r, g, b] = read_f32s(reader)?;
+			Variant::Color3(Color3::new(r, g, b))
+		}
+		TypeId::Color3uint8 => {
+			let [r, g, b] = read_array(reader)?;
+			Variant::Color3uint8(Color3uint8::new(r, g, b))
+		}
+		TypeId::ColorSequence => {
+			let length = read_length(reader).wrap_err("failed reading color sequence length")?;
+			let keypoints = (0..length)
+				.map(|_| {
+					let [time, r, g, b] = read_f32s(reader)?;
+					Ok(ColorSequenceKeypoint::new(time, Color3::new(r, g, b)))
+				})
+				.collect::<eyre::Result<_>>()?;
+
+			Variant::ColorSequence(ColorSequence { keypoints })
+		}
+		TypeId::Enum => Variant::Enum(Enum::from_u32(
+			u32::try_from(read_unsigned(reader)?).wrap_err("failed truncating Enum to u32")?,
+		)),
+		TypeId::Faces => Variant::Faces(
+			Faces::from_bits(read_u8(reader)?).ok_or_else(|| eyre!("invalid bits for Faces"))?,
+		),
+		TypeId::Float32 => Variant::Float32(read_f32s::<1>(reader)?[0]),
+		TypeId::Float64 => Variant::Float64(f64::from_le_bytes(read_array(reader)?)),
+		TypeId::Int32 => Variant::Int32(i32::from_le_bytes(read_array(reader)?)),
+		TypeId::MaterialColors => Variant::MaterialColors(
+			MaterialColors::decode(&read_array::<69>(reader)?)
+				.map_err(|e| eyre!("failed decoding MaterialColors: {e}"))?,
+		),
+		TypeId::NumberRange => {
+			let [min, max] = read_f32s(reader)?;
+			Variant::NumberRange(NumberRange::new(min, max))
+		}
+		TypeId::NumberSequence => {
+			let length = read_length(reader).wrap_err("failed reading number sequence length")?;
+			let keypoints = (0..length)
+				.map(|_| {
+					let [envelope, time, value] = read_f32s(reader)?;
+					Ok(NumberSequenceKeypoint::new(time, value, envelope))
+				})
+				.collect::<eyre::Result<_>>()?;
+
+			Variant::NumberSequence(NumberSequence { keypoints })
+		}
+		TypeId::None => return Ok(DecodedValue::None),
+		TypeId::DefaultPhysicalProperties => Variant::PhysicalProperties(PhysicalProperties::Default),
+		TypeId::CustomPhysicalProperties => {
+			let [
+				density,
+				elasticity,
+				elasticity_weight,
+				friction,
+				friction_weight,
+			] = read_f32s(reader)?;
+
+			// acoustic absorption is not encoded, 1.0 is what Roblox defaults it to
+			Variant::PhysicalProperties(PhysicalProperties::Custom(CustomPhysicalProperties::new(
+				density,
+				friction,
+				elasticity,
+				friction_weight,
+				elasticity_weight,
+				1.0,
+			)))
+		}
+		TypeId::Ray => {
+			let [
+				direction_x,
+				direction_y,
+				direction_z,
+				origin_x,
+				origin_y,
+				origin_z,
+			] = read_f32s(reader)?;
+
+			Variant::Ray(Ray::new(
+				Vector3::new(origin_x, origin_y, origin_z),
+				Vector3::new(direction_x, direction_y, direction_z),
+			))
+		}
+		TypeId::Rect => {
+			let [min_x, min_y, max_x, max_y] = read_f32s(reader)?;
+			Variant::Rect(Rect::new(
+				Vector2::new(min_x, min_y),
+				Vector2::new(max_x, max_y),
+			))
+		}
+		TypeId::Ref => return Ok(DecodedValue::Ref(read_unsigned(reader)?)),
+		TypeId::Region3 => {
+			let [min_x, min_y, min_z, max_x, max_y, max_z] = read_f32s(reader)?;
+			Variant::Region3(Region3::new(
+				Vector3::new(min_x, min_y, min_z),
+				Vector3::new(max_x, max_y, max_z),
+			))
+		}
+		TypeId::Region3int16 => {
+			let [min_x, min_y, min_z, max_x, max_y, max_z] = read_i16s(reader)?;
+			Variant::Region3int16(Region3int16::new(
+				Vector3int16::new(min_x, min_y, min_z),
+				Vector3int16::new(max_x, max_y, max_z),
+			))
+		}
+		TypeId::SecurityCapabilities => Variant::SecurityCapabilities(SecurityCapabilities::from_bits(
+			u64::from_le_bytes(read_array(reader)?),
+		)),
+		TypeId::BinaryString => Variant::BinaryString(BinaryString::from(read_varstring(reader)?)),
+		TypeId::Tags => {
+			let length = read_length(reader).wrap_err("failed reading tags length")?;
+			let mut tags = Tags::new();
+
+			for _ in 0..length {
+				tags.push(&read_interned_string(reader, strings).wrap_err("failed reading tag")?);
+			}
+
+			Variant::Tags(tags)
+		}
+		TypeId::UDim => {
+			let offset = i32::from_le_bytes(read_array(reader)?);
+			let [scale] = read_f32s(reader)?;
+			Variant::UDim(UDim::new(scale, offset))
+		}
+		TypeId::UDim2 => {
+			let x_offset = i32::from_le_bytes(read_array(reader)?);
+			let y_offset = i32::from_le_bytes(read_array(reader)?);
+			let [x_scale, y_scale] = read_f32s(reader)?;
+
+			Variant::UDim2(UDim2::new(
+				UDim::new(x_scale, x_offset),
+				UDim::new(y_scale, y_offset),
+			))
+		}
+		TypeId::Vector2 => {
+			let [x, y] = read_f32s(reader)?;
+			Variant::Vector2(Vector2::new(x, y))
+		}
+		TypeId::Vector2int16 => {
+			let [x, y] = read_i16s(reader)?;
+			Variant::Vector2int16(Vector2int16::new(x, y))
+		}
+		TypeId::Vector3 => {
+			let [x, y, z] = read_f32s(reader)?;
+			Variant::Vector3(Vector3::new(x, y, z))
+		}
+		TypeId::Vector3int16 => {
+			let [x, y, z] = read_i16s(reader)?;
+			Variant::Vector3int16(Vector3int16::new(x, y, z))
+		}
+		TypeId::Font => {
+			let family = read_utf8_nullstring(reader).wrap_err("failed reading family for Font")?;
+			let weight = u16::from_le_bytes(read_array(reader)?);
+			let style = read_u8(reader)?;
+
+			Variant::Font(Font::new(
+				&family,
+				FontWeight::from_u16(weight)
+					.ok_or_else(|| eyre!("font weight {weight} is not supported or is invalid"))?,
+				FontStyle::from_u8(style)
+					.ok_or_else(|| eyre!("font style {style} is not supported or is invalid"))?,
+			))
+		}
+		TypeId::ContentNone => Variant::Content(Content::none()),
+		TypeId::ContentObject => return Ok(DecodedValue::ContentObject(read_unsigned(reader)?)),
+		TypeId::ContentUri => Variant::Content(Content::from_uri(
+			read_utf8_nullstring(reader).wrap_err("failed reading uri for Content")?,
+		)),
+	};
+
+	Ok(DecodedValue::Variant(variant))
+}
+
+fn read_referent(reader: &mut impl BufRead, strings: &Strings) -> eyre::Result<Option<u64>> {
+	match read_variant(reader, strings)? {
+		DecodedValue::Ref(referent) => Ok(Some(referent)),
+		DecodedValue::None => Ok(None),
+		_ => bail!("expected a Ref or None variant"),
+	}
+}
+
+/// Reads and validates the header which starts every payload (or patch, with [`PATCH_MAGIC`]), returning the payload's [`FormatFlags`].
+fn read_header(reader: &mut impl BufRead, expected_magic: [u8; 6]) -> eyre::Result<FormatFlags> {
+	let magic = read_array::<{ FORMAT_MAGIC.len() }>(reader)
+		.wrap_err("payload is too short to contain a header")?;
+	ensure!(
+		magic == expected_magic,
+		if expected_magic == PATCH_MAGIC {
+			"payload is not an azalea patch (missing magic header)"
+		} else {
+			"payload is not an azalea payload (missing magic header), it was likely encoded by an older azalea"
+		}
+	);
+
+	let version = read_u8(reader).wrap_err("failed reading format version")?;
+	ensure!(
+		version == FORMAT_VERSION,
+		"payload uses azalea format version {version}, but this decoder only supports version {FORMAT_VERSION}"
+	);
+
+	let flags = u16::from_le_bytes(read_array(reader).wrap_err("failed reading format flags")?);
+	FormatFlags::from_bits(flags)
+		.ok_or_else(|| eyre!("payload uses unknown format flags {flags:#06x}"))
+}
+
+/// Reads the [`RecordKind`] which starts every record of a [`FormatFlags::CLONED_SUBTREES`] payload.
+fn read_record_kind(reader: &mut impl BufRead, flags: FormatFlags) -> eyre::Result<RecordKind> {
+	if !flags.contains(FormatFlags::CLONED_SUBTREES) {
+		return Ok(RecordKind::Instance);
+	}
+
+	RecordKind::try_from(read_u8(reader).wrap_err("failed reading record kind")?)
+}
+
+/// Reads a [`RecordKind::Clone`] record (without its kind).
+fn read_clone_record(reader: &mut impl BufRead, strings: &Strings) -> eyre::Result<DecodedClone> {
+	let name = read_utf8_varstring(reader).wrap_err("failed reading clone Name")?;
+
+	let read_required_referent = |reader: &mut _, kind: &str| -> eyre::Result<u64> {
+		read_referent(reader, strings)
+			.wrap_err_with(|| format!("failed reading clone {kind} referent"))?
+			.ok_or_else(|| eyre!("clone {name} has a nil {kind} referent"))
+	};
+	let source = read_required_referent(reader, "source")?;
+	let referent = read_required_referent(reader, "instance")?;
+	let parent = read_required_referent(reader, "parent")?;
+
+	let descendant_count = read_length(reader).wrap_err("failed reading clone descendant count")?;
+	let descendants = (0..descendant_count)
+		.map(|_| read_required_referent(reader, "descendant"))
+		.collect::<eyre::Result<_>>()?;
+
+	Ok(DecodedClone {
+		name,
+		source,
+		referent,
+		parent,
+		descendants,
+	})
+}
+
+/// Reads the parts of an instance which precede its properties, returning the instance (without properties) and its schema index.
+///
+/// `schemas` must be present if the payload was encoded with [`FormatFlags::PROPERTY_SCHEMAS`].
+fn read_instance_record(
+	reader: &mut impl BufRead,
+	strings: &Strings,
+	schemas: Option<&[Schema]>,
+) -> eyre::Result<(DecodedInstance, Option<usize>)> {
+	let name = read_utf8_varstring(reader).wrap_err("failed reading instance Name")?;
+
+	let schema_index = match schemas {
+		Some(schemas) => {
+			let index = read_length(reader).wrap_err("failed reading schema index")?;
+			ensure!(
+				index < schemas.len(),
+				"schema index {index} is out of bounds"
+			);
+
+			Some(index)
+		}
+		None => None,
+	};
+
+	let class = match (schemas, schema_index) {
+		(Some(schemas), Some(index)) => schemas[index].class.clone(),
+		_ => read_interned_string(reader, strings)
+			.wrap_err("failed reading interned string for instance ClassName")?,
+	};
+
+	let referent = read_referent(reader, strings)
+		.wrap_err("failed reading instance referent")?
+		.ok_or_else(|| eyre!("instance {name} has a nil referent"))?;
+	let parent =
+		read_referent(reader, strings).wrap_err("failed reading instance parent referent")?;
+
+	Ok((
+		DecodedInstance {
+			name,
+			class,
+			referent,
+			parent,
+			properties: Vec::new(),
+		},
+		schema_index,
+	))
+}
+
+/// Decodes a single instance (without any children) from a reader which implements [`BufRead`].
+///
+/// `schemas` must be present if the payload was encoded with [`FormatFlags::PROPERTY_SCHEMAS`].
+fn decode_instance(
+	reader: &mut impl BufRead,
+	strings: &Strings,
+	schemas: Option<&[Schema]>,
+) -> eyre::Result<DecodedInstance> {
+	let (mut instance, schema_index) = read_instance_record(reader, strings, schemas)?;
+
+	instance.properties = if let (Some(schemas), Some(index)) = (schemas, schema_index) {
+		schemas[index]
+			.property_names
+			.iter()
+			.map(|property| {
+				let value = read_variant(reader, strings)
+					.wrap_err_with(|| format!("failed reading property variant for {property}"))?;
+
+				Ok((property.clone(), value))
+			})
+			.collect::<eyre::Result<_>>()?
+	} else {
+		read_properties(reader, strings)?
+	};
+
+	Ok(instance)
+}
+
+/// Reads a u16 count of interned property names and their values.
+fn read_properties(
+	reader: &mut impl BufRead,
+	strings: &Strings,
+) -> eyre::Result<Vec<(String, DecodedValue)>> {
+	let properties_length =
+		u16::from_le_bytes(read_array(reader).wrap_err("failed reading properties length")?);
+
+	let mut properties = Vec::with_capacity(properties_length.into());
+	for _ in 0..properties_length {
+		let property =
+			read_interned_string(reader, strings).wrap_err("failed reading property name")?;
+		let value = read_variant(reader, strings)
+			.wrap_err_with(|| format!("failed reading property variant for {property}"))?;
+
+		properties.push((property, value));
+	}
+
+	Ok(properties)
+}
+
+/// Reads a column of `count` values, see [`ColumnKind`].
+fn read_column(
+	reader: &mut impl BufRead,
+	strings: &Strings,
+	count: usize,
+) -> eyre::Result<Vec<DecodedValue>> {
+	let kind = ColumnKind::try_from(read_u8(reader).wrap_err("failed reading column kind")?)?;
+
+	if kind == ColumnKind::Variants {
+		return (0..count)
+			.map(|_| read_variant(reader, strings).wrap_err("failed reading column variant"))
+			.collect();
+	}
+
+	let type_id = read_u8(reader).wrap_err("failed reading column type id")?;
+	let word_count = TypeId::try_from(type_id)?
+		.word_count()
+		.ok_or_else(|| eyre!("column type id {type_id} is not made of words"))?;
+
+	let words_length = count
+		.checked_mul(word_count)
+		.filter(|words_length| words_length.checked_mul(4).is_some())
+		.ok_or_else(|| eyre!("column of {count} values is too long"))?;
+	let mut words = read_bytes(reader, words_length * 4).wrap_err("failed reading column words")?;
+
+	if kind == ColumnKind::ShuffledWords {
+		let shuffled = words.clone();
+		for (index, byte) in shuffled.into_iter().enumerate() {
+			words[(index % words_length) * 4 + index / words_length] = byte;
+		}
+	}
+
+	// rebuild every value as it would be laid out in a row payload, so read_variant can decode it
+	let stride = 1 + word_count * 4;
+	let mut rows = vec![0; count * stride];
+	for (index, word) in words.chunks_exact(4).enumerate() {
+		let (component, value) = (index / count, index % count);
+		rows[value * stride] = type_id;
+		rows[value * stride + 1 + component * 4..][..4].copy_from_slice(word);
+	}
+
+	let mut rows = rows.as_slice();
+	(0..count)
+		.map(|_| read_variant(&mut rows, strings).wrap_err("failed reading column value"))
+		.collect()
+}
+
+/// Returns the subtree of `referent` in encoding order, see [`RecordKind::Clone`].
+fn subtree_referents(weak_dom: &WeakDom, referent: Ref) -> Vec<Ref> {
+	let mut referents = Vec::new();
+
+	let mut stack = vec![referent];
+	while let Some(referent) = stack.pop() {
+		referents.push(referent);
+		stack.extend(
+			weak_dom
+				.get_by_ref(referent)
+				.unwrap()
+				.children()
+				.iter()
+				.rev(),
+		);
+	}
+
+	referents
+}
+
+/// Inserts decoded instances into a [`WeakDom`], and resolves referent properties once every instance is inserted.
+#[derive(Default)]
+struct DomBuilder {
+	weak_dom: Option<WeakDom>,
+	referent_map: HashMap<u64, Ref>,
+	/// The inverse of `referent_map`, cloned subtrees are matched to their source through it.
+	payload_referents: HashMap<Ref, u64>,
+
+	// referent properties can point to instances which haven't been decoded yet, so they are applied last
+	late_properties: Vec<(Ref, String, DecodedValue)>,
+}
+
+impl DomBuilder {
+	fn insert(&mut self, instance: DecodedInstance) -> eyre::Result<()> {
+		let mut builder =
+			InstanceBuilder::with_property_capacity(instance.class.as_str(), instance.properties.len())
+				.with_name(instance.name);
+
+		let referent = builder.referent();
+
+		for (property, value) in instance.properties {
+			match value {
+				DecodedValue::Variant(variant) => builder.add_property(property.as_str(), variant),
+				DecodedValue::None => {}
+				value => self.late_properties.push((referent, property, value)),
+			}
+		}
+
+		match (instance.parent, self.weak_dom.as_mut()) {
+			(None, None) => self.weak_dom = Some(WeakDom::new(builder)),
+			(None, Some(_)) => bail!("there are multiple root referents in the hierarchy"),
+			(Some(parent), Some(weak_dom)) => {
+				let parent = *self.referent_map.get(&parent).ok_or_else(|| {
+					eyre!(
+						"parent referent {parent} was not decoded before referent {}",
+						instance.referent
+					)
+				})?;
+
+				ensure!(
+					weak_dom.get_by_ref(parent).is_some(),
+					"parent of referent {} was removed before it was added",
+					instance.referent
+				);
+				weak_dom.insert(parent, builder);
+			}
+			(Some(parent), None) => {
+				bail!(
+					"parent referent {parent} was not decoded before referent {}",
+					instance.referent
+				)
+			}
+		}
+
+		self.register(instance.referent, referent)
+	}
+
+	fn register(&mut self, payload_referent: u64, referent: Ref) -> eyre::Result<()> {
+		ensure!(
+			self
+				.referent_map
+				.insert(payload_referent, referent)
+				.is_none(),
+			"referent {payload_referent} was decoded twice"
+		);
+		self.payload_referents.insert(referent, payload_referent);
+
+		Ok(())
+	}
+
+	/// Clones the subtree of `clone.source`, like `:Clone()` does in decoders.
+	fn clone_subtree(&mut self, clone: DecodedClone) -> eyre::Result<()> {
+		let weak_dom = self
+			.weak_dom
+			.as_mut()
+			.ok_or_else(|| eyre!("clone {} precedes the root instance", clone.name))?;
+		let find = |payload_referent: u64| {
+			self
+				.referent_map
+				.get(&payload_referent)
+				.copied()
+				.ok_or_else(|| {
+					eyre!(
+						"referent {payload_referent} was not decoded before clone {}",
+						clone.referent
+					)
+				})
+		};
+		let (source, parent) = (find(clone.source)?, find(clone.parent)?);
+
+		let cloned = weak_dom.clone_within(source);
+		weak_dom.transfer_within(cloned, parent);
+		weak_dom.get_by_ref_mut(cloned).unwrap().name = clone.name;
+
+		let sources = subtree_referents(weak_dom, source);
+		let clones = subtree_referents(weak_dom, cloned);
+		ensure!(
+			clones.len() == clone.descendants.len() + 1,
+			"clone {} has {} descendant referents, but its source has {} descendants",
+			clone.referent,
+			clone.descendants.len(),
+			clones.len() - 1
+		);
+
+		let mut cloned_referents = HashMap::new();
+		let mut cloned_payload_referents = HashMap::new();
+		for ((source, cloned), payload_referent) in sources
+			.into_iter()
+			.zip(clones)
+			.zip(std::iter::once(clone.referent).chain(clone.descendants))
+		{
+			cloned_referents.insert(source, cloned);
+			cloned_payload_referents.insert(self.payload_referents[&source], payload_referent);
+			self.register(payload_referent, cloned)?;
+		}
+
+		// late properties aren't set yet, so they are copied to the clone (pointing into the clone instead of the source)
+		let remap = |target: &u64| *cloned_payload_referents.get(target).unwrap_or(target);
+		let cloned_properties = self
+			.late_properties
+			.iter()
+			.filter_map(|(referent, property, value)| {
+				let value = match value {
+					DecodedValue::Ref(target) => DecodedValue::Ref(remap(target)),
+					DecodedValue::ContentObject(target) => DecodedValue::ContentObject(remap(target)),
+					value => value.clone(),
+				};
+
+				Some((*cloned_referents.get(referent)?, property.clone(), value))
+			})
+			.collect::<Vec<_>>();
+		self.late_properties.extend(cloned_properties);
+
+		Ok(())
+	}
+
+	fn resolve(&self, payload_referent: u64) -> eyre::Result<Ref> {
+		self
+			.referent_map
+			.get(&payload_referent)
+			.copied()
+			.ok_or_else(|| eyre!("referent {payload_referent} was not decoded or located"))
+	}
+
+	/// Like [`DomBuilder::resolve`], but fails if the instance was removed (by an earlier patch record).
+	fn resolve_existing(&self, payload_referent: u64) -> eyre::Result<Ref> {
+		let referent = self.resolve(payload_referent)?;
+		ensure!(
+			self
+				.weak_dom
+				.as_ref()
+				.is_some_and(|weak_dom| weak_dom.get_by_ref(referent).is_some()),
+			"referent {payload_referent} was already removed"
+		);
+
+		Ok(referent)
+	}
+
+	/// Applies a [`PatchRecordKind::Change`] record to the instance of `payload_referent`.
+	fn change(
+		&mut self,
+		payload_referent: u64,
+		properties: Vec<(String, DecodedValue)>,
+	) -> eyre::Result<()> {
+		let referent = self.resolve(payload_referent)?;
+		let instance = self
+			.weak_dom
+			.as_mut()
+			.and_then(|weak_dom| weak_dom.get_by_ref_mut(referent))
+			.ok_or_else(|| eyre!("referent {payload_referent} was removed before it was changed"))?;
+
+		for (property, value) in properties {
+			match (property.as_str(), value) {
+				("Name", DecodedValue::Variant(Variant::String(name))) => instance.name = name,
+				(_, DecodedValue::Variant(variant)) => {
+					instance
+						.properties
+						.insert(property.as_str().into(), variant);
+				}
+				(_, DecodedValue::None) => {}
+				(_, value) => self.late_properties.push((referent, property, value)),
+			}
+		}
+
+		Ok(())
+	}
+
+	fn finish(self) -> eyre::Result<WeakDom> {
+		let mut weak_dom = self
+			.weak_dom
+			.ok_or_else(|| eyre!("no root referent in hierarchy"))?;
+
+		for (referent, property, value) in self.late_properties {
+			let variant = match value {
+				DecodedValue::Ref(target) => Variant::Ref(
+					*self
+						.referent_map
+						.get(&target)
+						.ok_or_else(|| eyre!("property {property} points to unknown referent {target}"))?,
+				),
+				DecodedValue::ContentObject(target) => Variant::Content(Content::from_referent(
+					*self
+						.referent_map
+						.get(&target)
+						.ok_or_else(|| eyre!("property {property} points to unknown referent {target}"))?,
+				)),
+				DecodedValue::Variant(..) | DecodedValue::None => unreachable!(),
+			};
+
+			weak_dom
+				.get_by_ref_mut(referent)
+				.ok_or_else(|| eyre!("property {property} belongs to an instance which was removed"))?
+				.properties
+				.insert(property.as_str().into(), variant);
+		}
+
+		Ok(weak_dom)
+	}
+}
+
+/// Decodes a payload produced by [`crate::encoder::encode_dom_into_writer`] from a reader which implements [`BufRead`].
+///
+/// The instance without a parent becomes the root of the returned [`WeakDom`].
+pub fn decode_dom_from_reader(mut reader: impl BufRead) -> eyre::Result<WeakDom> {
+	let flags = read_header(&mut reader, FORMAT_MAGIC)?;
+	let strings = Strings {
+		interned: read_string_table(&mut reader)?,
+		blobs: if flags.contains(FormatFlags::BLOB_TABLE) {
+			read_blob_table(&mut reader)?
+		} else {
+			Vec::new()
+		},
+	};
+	let schemas = if flags.contains(FormatFlags::PROPERTY_SCHEMAS) {
+		Some(read_schema_table(&mut reader, &strings)?)
+	} else {
+		None
+	};
+
+	let mut dom_builder = DomBuilder::default();
+
+	if flags.contains(FormatFlags::COLUMNAR_LAYOUT) {
+		let schemas = schemas
+			.as_deref()
+			.ok_or_else(|| eyre!("columnar payloads must use property schemas"))?;
+
+		let instance_count = read_length(&mut reader).wrap_err("failed reading instance count")?;
+		let mut records = Vec::new();
+		let mut instances_by_schema = vec![Vec::new(); schemas.len()];
+
+		for index in 0..instance_count {
+			if read_record_kind(&mut reader, flags)? == RecordKind::Clone {
+				records.push(DecodedRecord::Clone(read_clone_record(
+					&mut reader,
+					&strings,
+				)?));
+				continue;
+			}
+
+			let (instance, schema_index) = read_instance_record(&mut reader, &strings, Some(schemas))?;
+			// read_instance_record always returns a schema index when schemas are passed
+			instances_by_schema[schema_index.unwrap()].push(index);
+			records.push(DecodedRecord::Instance(instance));
+		}
+
+		for (schema, members) in schemas.iter().zip(&instances_by_schema) {
+			for property in &schema.property_names {
+				let values = read_column(&mut reader, &strings, members.len())
+					.wrap_err_with(|| format!("failed reading column for {}.{property}", schema.class))?;
+
+				for (&index, value) in members.iter().zip(values) {
+					if let DecodedRecord::Instance(instance) = &mut records[index] {
+						instance.properties.push((property.clone(), value));
+					}
+				}
+			}
+		}
+
+		for record in records {
+			match record {
+				DecodedRecord::Instance(instance) => dom_builder.insert(instance)?,
+				DecodedRecord::Clone(clone) => dom_builder.clone_subtree(clone)?,
+			}
+		}
+
+		return dom_builder.finish();
+	}
+
+	while !reader
+		.fill_buf()
+		.wrap_err("failed reading payload")?
+		.is_empty()
+	{
+		if read_record_kind(&mut reader, flags)? == RecordKind::Clone {
+			dom_builder.clone_subtree(read_clone_record(&mut reader, &strings)?)?;
+			continue;
+		}
+
+		dom_builder.insert(decode_instance(&mut reader, &strings, schemas.as_deref())?)?;
+	}
+
+	dom_builder.finish()
+}
+
+/// Reads the path of a [`PatchRecordKind::Locate`] record, and returns the instance it leads to below `root`.
+fn locate_instance(
+	reader: &mut impl BufRead,
+	strings: &Strings,
+	weak_dom: &WeakDom,
+	root: Ref,
+) -> eyre::Result<Ref> {
+	let length = read_length(reader).wrap_err("failed reading path length")?;
+
+	let mut referent = root;
+	for _ in 0..length {
+		let name = read_interned_string(reader, strings).wrap_err("failed reading path name")?;
+		let index = read_length(reader).wrap_err("failed reading sibling index")?;
+
+		referent = *weak_dom
+			.get_by_ref(referent)
+			.unwrap()
+			.children()
+			.iter()
+			.filter(|child| weak_dom.get_by_ref(**child).unwrap().name == name)
+			.nth(index)
+			.ok_or_else(|| {
+				eyre!("failed locating {name} (index {index}), the tree does not match the patch")
+			})?;
+	}
+
+	Ok(referent)
+}
+
+/// Applies a patch produced by [`crate::diff::diff_doms_into_writer`] from a reader which implements [`BufRead`].
+///
+/// `weak_dom` must match the old model of the patch, and is returned updated to the new model.
+pub fn apply_patch_from_reader(
+	weak_dom: WeakDom,
+	mut reader: impl BufRead,
+) -> eyre::Result<WeakDom> {
+	let flags = read_header(&mut reader, PATCH_MAGIC)?;
+	ensure!(
+		flags.is_empty(),
+		"patches do not support format flags, got {flags:?}"
+	);
+
+	let strings = Strings {
+		interned: read_string_table(&mut reader)?,
+		blobs: Vec::new(),
+	};
+	let root = crate::diff::model_root(&weak_dom)?;
+
+	let mut dom_builder = DomBuilder {
+		weak_dom: Some(weak_dom),
+		..DomBuilder::default()
+	};
+
+	let read_required_referent = |reader: &mut _| -> eyre::Result<u64> {
+		read_referent(reader, &strings)?.ok_or_else(|| eyre!("patch record has a nil referent"))
+	};
+
+	while !reader
+		.fill_buf()
+		.wrap_err("failed reading patch")?
+		.is_empty()
+	{
+		let kind = read_u8(&mut reader).wrap_err("failed reading patch record kind")?;
+
+		match PatchRecordKind::try_from(kind)? {
+			PatchRecordKind::Locate => {
+				let payload_referent = read_required_referent(&mut reader)?;
+				let referent = locate_instance(
+					&mut reader,
+					&strings,
+					dom_builder.weak_dom.as_ref().unwrap(),
+					root,
+				)?;
+				dom_builder.register(payload_referent, referent)?;
+			}
+			PatchRecordKind::Add => {
+				dom_builder.insert(decode_instance(&mut reader, &strings, None)?)?;
+			}
+			PatchRecordKind::Change => {
+				let payload_referent = read_required_referent(&mut reader)?;
+				let properties = read_properties(&mut reader, &strings)?;
+				dom_builder.change(payload_referent, properties)?;
+			}
+			PatchRecordKind::Reparent => {
+				let referent = dom_builder.resolve_existing(read_required_referent(&mut reader)?)?;
+				let parent = dom_builder.resolve_existing(read_required_referent(&mut reader)?)?;
+				let weak_dom = dom_builder.weak_dom.as_mut().unwrap();
+				ensure!(referent != root, "patch reparents the model root");
+				ensure!(
+					!subtree_referents(weak_dom, referent).contains(&parent),
+					"patch reparents an instance into its own subtree"
+				);
+
+				weak_dom.transfer_within(referent, parent);
+			}
+			PatchRecordKind::Remove => {
+				let referent = dom_builder.resolve_existing(read_required_referent(&mut reader)?)?;
+				ensure!(referent != root, "patch removes the model root");
+
+				dom_builder.weak_dom.as_mut().unwrap().destroy(referent);
+			}
+		}
+	}
+
+	dom_builder.finish()
+}
+
+/// A more concise version of [`apply_patch_from_reader`] for in-memory patches.
+pub fn apply_patch(weak_dom: WeakDom, patch: &[u8]) -> eyre::Result<WeakDom> {
+	apply_patch_from_reader(weak_dom, patch)
+}
+
+/// A more concise version of [`decode_dom_from_reader`] for in-memory payloads.
+pub fn decode_dom(payload: &[u8]) -> eyre::Result<WeakDom> {
+	decode_dom_from_reader(payload)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		emit::Requirements,
+		encoder::{
+			StringTable, encode_dom_into_writer, variant_referent, write_header, write_interned_string,
+			write_variant,
+		},
+		spec::HEADER_LENGTH,
+	};
+	use rbx_dom_weak::{
+		Instance,
+		types::{ContentType, UniqueId},
+	};
+
+	fn descendants_in_encoding_order(weak_dom: &WeakDom) -> Vec<&Instance> {
+		let mut instances = Vec::new();
+		let mut stack = vec![weak_dom.root_ref()];
+
+		while let Some(referent) = stack.pop() {
+			let instance = weak_dom.get_by_ref(referent).unwrap();
+			stack.extend(instance.children().iter().rev().copied());
+			instances.push(instance);
+		}
+
+		instances
+	}
+
+	/// Encodes a model, decodes it and then checks the decoded tree mirrors the original tree.
+	fn assert_round_trip(path: &str, requirements: Requirements) {
+		let original =
+			rbx_binary::from_reader(std::fs::File::open(path).unwrap()).expect("failed reading model");
+
+		assert_dom_round_trip(&original, requirements);
+	}
+
+	/// Like [`assert_round_trip`], but for a [`WeakDom`]. Returns the payload.
+	fn assert_dom_round_trip(original: &WeakDom, requirements: Requirements) -> Vec<u8> {
+		let mut payload = Vec::new();
+		encode_dom_into_writer(original, &mut payload, requirements).expect("failed encoding model");
+
+		let decoded = decode_dom(&payload).expect("failed decoding payload");
+		assert_doms_match(original, &decoded);
+
+		payload
+	}
+
+	/// Returns what decoding `value` results in (with referents mapped through `referents`), see the module documentation.
+	fn decoded_variant(value: &Variant, referents: &HashMap<Ref, Ref>) -> Option<Variant> {
+		Some(match value {
+			Variant::Ref(referent) if referent.is_none() => return None,
+			Variant::Ref(referent) => Variant::Ref(referents[referent]),
+			Variant::Content(content) => match content.value() {
+				ContentType::Object(referent) if referent.is_some() => {
+					Variant::Content(Content::from_referent(referents[referent]))
+				}
+				_ => value.clone(),
+			},
+			Variant::OptionalCFrame(cframe) => Variant::CFrame((*cframe)?),
+			Variant::Int64(int) => {
+				i32::try_from(*int).map_or(Variant::Float64(*int as f64), Variant::Int32)
+			}
+			Variant::ContentId(content) => Variant::String(content.as_str().to_owned()),
+			Variant::UniqueId(id) => Variant::String(id.to_string()),
+			Variant::EnumItem(item) => Variant::Enum(Enum::from_u32(item.value)),
+			Variant::SharedString(string) => Variant::BinaryString(string.data().to_vec().into()),
+			Variant::NetAssetRef(asset) => Variant::BinaryString(asset.data().to_vec().into()),
+			value => value.clone(),
+		})
+	}
+
+	/// Returns whether `a` equals `b`, where floats only have to be close to each other.
+	fn variants_match(a: &Variant, b: &Variant) -> bool {
+		match (a, b) {
+			(Variant::Float32(a), Variant::Float32(b)) => {
+				(a - b).abs() <= f32::EPSILON * a.abs().max(1.0)
+			}
+			(Variant::Float64(a), Variant::Float64(b)) => {
+				(a - b).abs() <= f64::EPSILON * a.abs().max(1.0)
+			}
+			_ => a == b,
+		}
+	}
+
+	/// Checks `decoded` mirrors `original`, as far as encoding is lossless.
+	fn assert_doms_match(original: &WeakDom, decoded: &WeakDom) {
+		let original_instances = descendants_in_encoding_order(original);
+		let decoded_instances = descendants_in_encoding_order(decoded);
+		assert_eq!(original_instances.len(), decoded_instances.len());
+
+		let referents = original_instances
+			.iter()
+			.zip(&decoded_instances)
+			.map(|(original, decoded)| (original.referent(), decoded.referent()))
+			.collect::<HashMap<_, _>>();
+
+		for (original, decoded) in original_instances.iter().zip(&decoded_instances) {
+			assert_eq!(original.name, decoded.name);
+			assert_eq!(original.class, decoded.class);
+
+			let mut decoded_properties = 0;
+			for (property, value) in &original.properties {
+				let expected = decoded_variant(value, &referents);
+				let decoded_value = decoded.properties.get(property);
+				decoded_properties += usize::from(decoded_value.is_some());
+
+				match (&expected, decoded_value) {
+					(Some(expected), Some(decoded_value)) => assert!(
+						variants_match(expected, decoded_value),
+						"{}.{property} was decoded as {decoded_value:?} instead of {expected:?}",
+						original.name
+					),
+					(None, None) => {}
+					_ => panic!(
+						"{}.{property} was decoded as {decoded_value:?} instead of {expected:?}",
+						original.name
+					),
+				}
+			}
+
+			assert_eq!(
+				decoded_properties,
+				decoded.properties.len(),
+				"{} has properties which it did not have before encoding",
+				original.name
+			);
+		}
+	}
+
+	#[test]
+	fn round_trip_test_models() {
+		for entry in std::fs::read_dir("encoding/testRbxms").unwrap() {
+			let path = entry.unwrap().path();
+			if path
+				.extension()
+				.is_some_and(|extension| extension == "rbxm")
+			{
+				assert_round_trip(path.to_str().unwrap(), Requirements::empty());
+				assert_round_trip(path.to_str().unwrap(), Requirements::PROPERTY_SCHEMAS);
+				assert_round_trip(
+					path.to_str().unwrap(),
+					Requirements::COLUMNAR_LAYOUT | Requirements::BYTE_SHUFFLE,
+				);
+				assert_round_trip(path.to_str().unwrap(), Requirements::DEDUPLICATE_SUBTREES);
+				assert_round_trip(
+					path.to_str().unwrap(),
+					Requirements::COLUMNAR_LAYOUT | Requirements::DEDUPLICATE_SUBTREES,
+				);
+				assert_round_trip(path.to_str().unwrap(), Requirements::BLOB_TABLE);
+			}
+		}
+	}
+
+	#[test]
+	fn round_trip_examples() {
+		assert_round_trip("examples/attributes-and-tags.rbxm", Requirements::empty());
+		assert_round_trip("examples/fusion-0.3-release.rbxm", Requirements::empty());
+	}
+
+	#[test]
+	fn round_trip_property_schemas() {
+		assert_round_trip(
+			"examples/attributes-and-tags.rbxm",
+			Requirements::PROPERTY_SCHEMAS,
+		);
+		assert_round_trip(
+			"examples/fusion-0.3-release.rbxm",
+			Requirements::PROPERTY_SCHEMAS,
+		);
+	}
+
+	#[test]
+	fn round_trip_columnar_layout() {
+		for requirements in [
+			Requirements::COLUMNAR_LAYOUT,
+			Requirements::COLUMNAR_LAYOUT | Requirements::BYTE_SHUFFLE,
+		] {
+			assert_round_trip("examples/attributes-and-tags.rbxm", requirements);
+			assert_round_trip("examples/fusion-0.3-release.rbxm", requirements);
+		}
+	}
+
+	#[test]
+	fn round_trip_cloned_subtrees() {
+		let mut original = WeakDom::new(InstanceBuilder::new("DataModel"));
+		let model = original.insert(original.root_ref(), InstanceBuilder::new("Model"));
+		let target = original.insert(model, InstanceBuilder::new("Part").with_name("Target"));
+
+		let mut first_handle = Ref::none();
+		for index in 0..10 {
+			let tool = original.insert(
+				model,
+				InstanceBuilder::new("Tool").with_name(format!("Tool{index}")),
+			);
+			let handle = original.insert(
+				tool,
+				InstanceBuilder::new("Part")
+					.with_name("Handle")
+					.with_property("Size", Vector3::new(1.0, 2.0, 3.0)),
+			);
+			let blade = original.insert(tool, InstanceBuilder::new("Part").with_name("Blade"));
+
+			// one referent inside of the subtree, and one outside of it
+			original.insert(
+				handle,
+				InstanceBuilder::new("WeldConstraint")
+					.with_property("Part0", handle)
+					.with_property("Part1", blade),
+			);
+			original.insert(
+				blade,
+				InstanceBuilder::new("ObjectValue").with_property("Value", target),
+			);
+
+			if index == 0 {
+				first_handle = handle;
+			}
+		}
+
+		// referents pointing into a cloned subtree
+		let last_tool = *original
+			.get_by_ref(model)
+			.unwrap()
+			.children()
+			.last()
+			.unwrap();
+		let last_handle = original.get_by_ref(last_tool).unwrap().children()[0];
+		original.insert(
+			model,
+			InstanceBuilder::new("ObjectValue").with_property("Value", last_handle),
+		);
+		original.insert(
+			model,
+			InstanceBuilder::new("ObjectValue").with_property("Value", first_handle),
+		);
+
+		let full = assert_dom_round_trip(&original, Requirements::empty());
+		for requirements in [
+			Requirements::DEDUPLICATE_SUBTREES,
+			Requirements::DEDUPLICATE_SUBTREES | Requirements::COLUMNAR_LAYOUT,
+		] {
+			let deduplicated = assert_dom_round_trip(&original, requirements);
+			assert!(deduplicated.len() < full.len());
+		}
+	}
+
+	#[test]
+	fn round_trip_blob_table() {
+		let package_source = "return function(...)\n\treturn select(\"#\", ...)\nend\n".repeat(8);
+		let mesh_data = BinaryString::from((0..=255).collect::<Vec<u8>>());
+
+		let mut original = WeakDom::new(InstanceBuilder::new("Folder"));
+		for index in 0..4 {
+			let package = original.insert(
+				original.root_ref(),
+				InstanceBuilder::new("ModuleScript")
+					.with_name(format!("Package{index}"))
+					.with_property("Source", package_source.as_str()),
+			);
+			original.insert(
+				package,
+				InstanceBuilder::new("Folder").with_property("Data", mesh_data.clone()),
+			);
+		}
+
+		// neither of these repeat, so they stay inline
+		original.insert(
+			original.root_ref(),
+			InstanceBuilder::new("ModuleScript").with_property("Source", "return nil"),
+		);
+		original.insert(
+			original.root_ref(),
+			InstanceBuilder::new("ModuleScript").with_property("Source", package_source.to_uppercase()),
+		);
+
+		let full = assert_dom_round_trip(&original, Requirements::empty());
+		for requirements in [
+			Requirements::BLOB_TABLE,
+			Requirements::BLOB_TABLE | Requirements::COLUMNAR_LAYOUT,
+			Requirements::BLOB_TABLE | Requirements::DEDUPLICATE_SUBTREES,
+		] {
+			let payload = assert_dom_round_trip(&original, requirements);
+			assert!(payload.len() + 3 * package_source.len() < full.len());
+
+			let decoded = decode_dom(&payload).unwrap();
+			for folder in decoded
+				.descendants()
+				.filter(|instance| instance.class == "Folder" && instance.referent() != decoded.root_ref())
+			{
+				assert_eq!(
+					folder.properties.get(&rbx_dom_weak::ustr("Data")),
+					Some(&Variant::BinaryString(mesh_data.clone()))
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn apply_patches() {
+		let rescued_id = UniqueId::new(1, 2, 3);
+
+		let mut old = WeakDom::new(InstanceBuilder::new("DataModel"));
+		let old_root = old.insert(
+			old.root_ref(),
+			InstanceBuilder::new("Model").with_name("Root"),
+		);
+		let kept = old.insert(
+			old_root,
+			InstanceBuilder::new("Part")
+				.with_name("Kept")
+				.with_property("Size", Vector3::new(1.0, 1.0, 1.0)),
+		);
+		old.insert(old_root, InstanceBuilder::new("Part").with_name("Moved"));
+		let removed = old.insert(
+			old_root,
+			InstanceBuilder::new("Folder").with_name("Removed"),
+		);
+		old.insert(removed, InstanceBuilder::new("Part").with_name("Inner"));
+		old.insert(
+			removed,
+			InstanceBuilder::new("StringValue")
+				.with_name("Rescued")
+				.with_property("UniqueId", rescued_id)
+				.with_property("Value", "saved"),
+		);
+		old.insert(
+			old_root,
+			InstanceBuilder::new("Script").with_property("Source", "print(1)"),
+		);
+		old.insert(
+			old_root,
+			InstanceBuilder::new("ObjectValue")
+				.with_name("Pointer")
+				.with_property("Value", kept),
+		);
+		for transparency in [0.0, 0.5] {
+			old.insert(
+				old_root,
+				InstanceBuilder::new("Part")
+					.with_name("Twin")
+					.with_property("Transparency", transparency),
+			);
+		}
+
+		// added (and moved) instances end up after their existing siblings
+		let mut new = WeakDom::new(InstanceBuilder::new("DataModel"));
+		let new_root = new.insert(
+			new.root_ref(),
+			InstanceBuilder::new("Model").with_name("Renamed"),
+		);
+		let kept = new.insert(
+			new_root,
+			InstanceBuilder::new("Part")
+				.with_name("Kept")
+				.with_property("Size", Vector3::new(2.0, 2.0, 2.0))
+				.with_property("Anchored", true),
+		);
+		let pointer = new.insert(
+			new_root,
+			InstanceBuilder::new("ObjectValue").with_name("Pointer"),
+		);
+		for transparency in [0.0, 0.25] {
+			new.insert(
+				new_root,
+				InstanceBuilder::new("Part")
+					.with_name("Twin")
+					.with_property("Transparency", transparency),
+			);
+		}
+		let added = new.insert(new_root, InstanceBuilder::new("Folder").with_name("Added"));
+		let moved = new.insert(added, InstanceBuilder::new("Part").with_name("Moved"));
+		let fresh = new.insert(added, InstanceBuilder::new("Part").with_name("Fresh"));
+		new.insert(
+			fresh,
+			InstanceBuilder::new("WeldConstraint")
+				.with_property("Part0", fresh)
+				.with_property("Part1", kept),
+		);
+		new.insert(
+			new_root,
+			InstanceBuilder::new("StringValue")
+				.with_name("Rescued")
+				.with_property("UniqueId", rescued_id)
+				.with_property("Value", "saved"),
+		);
+		new.insert(
+			new_root,
+			InstanceBuilder::new("Script").with_property("Source", "print(2)"),
+		);
+		new
+			.get_by_ref_mut(pointer)
+			.unwrap()
+			.properties
+			.insert("Value".into(), Variant::Ref(moved));
+
+		let mut payload = Vec::new();
+		encode_dom_into_writer(&old, &mut payload, Requirements::empty()).unwrap();
+		let decoded = decode_dom(&payload).unwrap();
+
+		let mut patch = Vec::new();
+		crate::diff::diff_doms_into_writer(&old, &new, &mut patch, Requirements::empty()).unwrap();
+
+		let patched = apply_patch(decoded, &patch).unwrap();
+		assert_doms_match(&new, &patched);
+
+		// full payloads aren't patches, and patches need the tree they were made for
+		assert!(apply_patch(decode_dom(&payload).unwrap(), &payload).is_err());
+		assert!(apply_patch(patched, &patch).is_err());
+	}
+
+	#[test]
+	fn patch_removed_properties() {
+		let model = |transparency: Option<f32>, pointed: bool| {
+			let mut weak_dom = WeakDom::new(InstanceBuilder::new("DataModel"));
+			let root = weak_dom.insert(
+				weak_dom.root_ref(),
+				InstanceBuilder::new("Model").with_name("Root"),
+			);
+
+			let mut part = InstanceBuilder::new("Part").with_name("Part");
+			if let Some(transparency) = transparency {
+				part = part.with_property("Transparency", transparency);
+			}
+			let part = weak_dom.insert(root, part);
+			weak_dom.insert(
+				root,
+				InstanceBuilder::new("ObjectValue")
+					.with_name("Pointer")
+					.with_property("Value", if pointed { part } else { Ref::none() }),
+			);
+
+			weak_dom
+		};
+
+		// instances which lost properties are replaced, as properties can't be unset
+		let old = model(Some(0.5), true);
+		let new = model(None, false);
+
+		let mut payload = Vec::new();
+		encode_dom_into_writer(&old, &mut payload, Requirements::empty()).unwrap();
+		let mut patch = Vec::new();
+		crate::diff::diff_doms_into_writer(&old, &new, &mut patch, Requirements::empty()).unwrap();
+
+		let patched = apply_patch(decode_dom(&payload).unwrap(), &patch).unwrap();
+		assert_doms_match(&new, &patched);
+		for instance in patched.descendants() {
+			assert!(!instance.properties.contains_key(&"Transparency".into()));
+			assert!(
+				instance
+					.properties
+					.get(&"Value".into())
+					.is_none_or(|value| variant_referent(value).is_none())
+			);
+		}
+
+		// roots can't be replaced, and referents outside of the root can't be located
+		let mut old = model(None, false);
+		let old_root = old.root().children()[0];
+		old
+			.get_by_ref_mut(old_root)
+			.unwrap()
+			.properties
+			.insert("LevelOfDetail".into(), Variant::Enum(Enum::from_u32(1)));
+		assert!(
+			crate::diff::diff_doms_into_writer(&old, &new, &mut Vec::new(), Requirements::empty())
+				.is_err()
+		);
+
+		let mut outside = model(None, false);
+		let pointer = outside.root().children()[0];
+		let pointer = outside.get_by_ref(pointer).unwrap().children()[1];
+		let data_model = outside.root_ref();
+		outside
+			.get_by_ref_mut(pointer)
+			.unwrap()
+			.properties
+			.insert("Value".into(), Variant::Ref(data_model));
+		assert!(
+			crate::diff::diff_doms_into_writer(&new, &outside, &mut Vec::new(), Requirements::empty())
+				.is_err()
+		);
+	}
+
+	/// A record kind, the referents it takes, and (for locates) a path below the model root.
+	type TestPatchRecord<'a> = (PatchRecordKind, &'a [Ref], &'a [(&'a str, u64)]);
+
+	fn write_patch(records: &[TestPatchRecord]) -> Vec<u8> {
+		let mut referent_map = HashMap::new();
+		let mut string_table = StringTable::default();
+
+		let mut body = Vec::new();
+		for (kind, referents, path) in records {
+			body.push(*kind as u8);
+			for referent in *referents {
+				write_variant(
+					&mut body,
+					&Variant::Ref(*referent),
+					&mut referent_map,
+					&mut string_table,
+				)
+				.unwrap();
+			}
+
+			if *kind == PatchRecordKind::Locate {
+				leb128::write::unsigned(&mut body, path.len() as u64).unwrap();
+				for (name, index) in *path {
+					write_interned_string(&mut body, name, &mut string_table).unwrap();
+					leb128::write::unsigned(&mut body, *index).unwrap();
+				}
+			}
+		}
+
+		let mut patch = Vec::new();
+		write_header(&mut patch, PATCH_MAGIC, FormatFlags::empty()).unwrap();
+		string_table.write_into(&mut patch).unwrap();
+		patch.extend(body);
+
+		patch
+	}
+
+	#[test]
+	fn reject_invalid_patches() {
+		let mut old = WeakDom::new(InstanceBuilder::new("DataModel"));
+		let old_root = old.insert(
+			old.root_ref(),
+			InstanceBuilder::new("Model").with_name("Root"),
+		);
+		old.insert(old_root, InstanceBuilder::new("Part").with_name("Child"));
+
+		let mut payload = Vec::new();
+		encode_dom_into_writer(&old, &mut payload, Requirements::empty()).unwrap();
+
+		let (root, child) = (Ref::new(), Ref::new());
+		let locate_root = (PatchRecordKind::Locate, &[root][..], &[][..]);
+		let locate_child = (PatchRecordKind::Locate, &[child][..], &[("Child", 0)][..]);
+		let remove_child = (PatchRecordKind::Remove, &[child][..], &[][..]);
+
+		// stale or crafted patches must fail instead of panicking
+		for (records, message) in [
+			(
+				vec![locate_child, remove_child, remove_child],
+				"already removed",
+			),
+			(
+				vec![locate_root, (PatchRecordKind::Remove, &[root], &[])],
+				"removes the model root",
+			),
+			(
+				vec![
+					locate_root,
+					locate_child,
+					remove_child,
+					(PatchRecordKind::Reparent, &[child, root], &[]),
+				],
+				"already removed",
+			),
+			(
+				vec![
+					locate_root,
+					locate_child,
+					(PatchRecordKind::Reparent, &[root, child], &[]),
+				],
+				"reparents the model root",
+			),
+		] {
+			let error = apply_patch(decode_dom(&payload).unwrap(), &write_patch(&records)).unwrap_err();
+			assert!(error.to_string().contains(message), "{error}");
+		}
+	}
+
+	#[test]
+	fn reject_multiple_roots() {
+		let original = WeakDom::new(InstanceBuilder::new("Folder"));
+
+		let mut payload = Vec::new();
+		encode_dom_into_writer(&original, &mut payload, Requirements::empty()).unwrap();
+		// duplicate the instances, but not the header and string table
+		let mut instances = &payload[HEADER_LENGTH..];
+		read_string_table(&mut instances).unwrap();
+		let instances = instances.to_vec();
+		payload.extend(instances);
+
+		assert!(decode_dom(&payload).is_err());
+	}
+
+	#[test]
+	fn reject_malformed_lengths() {
+		// a string table holding one string, which claims to be far longer than the payload
+		let mut payload = Vec::new();
+		write_header(&mut payload, FORMAT_MAGIC, FormatFlags::empty()).unwrap();
+		leb128::write::unsigned(&mut payload, 1).unwrap();
+		leb128::write::unsigned(&mut payload, u64::MAX).unwrap();
+		assert!(decode_dom(&payload).is_err());
+
+		let strings = Strings {
+			interned: Vec::new(),
+			blobs: Vec::new(),
+		};
+		for type_id in [TypeId::ColorSequence, TypeId::NumberSequence] {
+			let mut value = vec![type_id as u8];
+			leb128::write::unsigned(&mut value, u64::MAX).unwrap();
+			assert!(read_variant(&mut value.as_slice(), &strings).is_err());
+		}
+	}
+
+	#[test]
+	fn reject_mismatched_headers() {
+		let original = WeakDom::new(InstanceBuilder::new("Folder"));
+
+		let mut payload = Vec::new();
+		encode_dom_into_writer(&original, &mut payload, Requirements::empty()).unwrap();
+		assert!(decode_dom(&payload).is_ok());
+
+		let mut newer_payload = payload.clone();
+		newer_payload[FORMAT_MAGIC.len()] = FORMAT_VERSION + 1;
+		assert!(decode_dom(&newer_payload).is_err());
+
+		assert!(decode_dom(&payload[FORMAT_MAGIC.len()..]).is_err());
+	}
+}
@@ -0,0 +1,298 @@
+//! A denser alternative to Base123 for Lua long bracket strings (`[==[...]==]`), which have no escapes and only end at
+//! their closing long bracket.
+//!
+//! Data is read as a bit stream of prefix codewords, each of which is written as one UTF-8 character:
+//! - 115 ASCII characters take 7 bits each, and the other 11 take 8 bits
+//! - every two byte character (U+0080 to U+07FF) takes 15 bits
+//!
+//! Null bytes and carriage returns are never written, as Lua normalizes line endings in long strings (and tools tend to drop null bytes).
+//! This packs about 7.1 bits into every byte of source, compared to Base123's 7, and the long bracket level is picked
+//! so that the closing long bracket can't appear in the encoded data.
+
+/// The ASCII characters written by the encoder, in the order of their codewords.
+pub const ASCII_CHARACTERS: [u8; 126] = {
+	let mut characters = [0; 126];
+	let mut character = 1;
+	let mut index = 0;
+
+	while index < characters.len() {
+		if character != b'\r' {
+			characters[index] = character;
+			index += 1;
+		}
+
+		character += 1;
+	}
+
+	characters
+};
+
+/// How many of [`ASCII_CHARACTERS`] have 7 bit codewords (0 to 114), the rest have 8 bit codewords.
+pub const SEVEN_BIT_CHARACTERS: u16 = 115;
+/// The first 8 bit codeword.
+pub const FIRST_EIGHT_BIT_CODEWORD: u16 = SEVEN_BIT_CHARACTERS << 1;
+/// The first 15 bit codeword, which is U+0080. The last one (32767) is U+07FF.
+pub const FIRST_FIFTEEN_BIT_CODEWORD: u16 =
+	(FIRST_EIGHT_BIT_CODEWORD + (ASCII_CHARACTERS.len() as u16 - SEVEN_BIT_CHARACTERS)) << 7;
+
+/// The decoder rejects data which the encoder could not have produced. Offsets are in bytes, into the encoded data.
+#[derive(Eq, PartialEq, Debug)]
+pub enum Error {
+	/// A byte which isn't one of [`ASCII_CHARACTERS`], or doesn't start a two byte character the encoder produces.
+	MalformedCharacter { offset: usize },
+	/// The data ended before `length` bytes were decoded.
+	EndOfStream,
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::MalformedCharacter { offset } => write!(f, "malformed character at offset {offset}"),
+			Self::EndOfStream => f.write_str("unexpected end of stream"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// Reads bits from the most significant bit of each byte, and zeroes past the end.
+struct BitReader<'data> {
+	data: &'data [u8],
+	position: usize,
+}
+
+impl BitReader<'_> {
+	fn peek(&self, count: usize) -> u16 {
+		(0..count).fold(0, |bits, index| {
+			let position = self.position + index;
+			let bit = self
+				.data
+				.get(position / 8)
+				.map_or(0, |byte| (byte >> (7 - position % 8)) & 1);
+
+			(bits << 1) | u16::from(bit)
+		})
+	}
+
+	const fn is_empty(&self) -> bool {
+		self.position >= self.data.len() * 8
+	}
+}
+
+/// Encodes `data` into valid UTF-8, without null bytes or carriage returns. The length of `data` is needed to decode it.
+#[must_use]
+pub fn dense_encode(data: &[u8]) -> Vec<u8> {
+	let mut output = Vec::with_capacity(data.len() * 9 / 8);
+	let mut reader = BitReader { data, position: 0 };
+
+	// the last codeword is padded with zeroes
+	while !reader.is_empty() {
+		let seven_bits = reader.peek(7);
+		if seven_bits < SEVEN_BIT_CHARACTERS {
+			output.push(ASCII_CHARACTERS[usize::from(seven_bits)]);
+			reader.position += 7;
+			continue;
+		}
+
+		let eight_bits = reader.peek(8);
+		if eight_bits < FIRST_FIFTEEN_BIT_CODEWORD >> 7 {
+			output.push(
+				ASCII_CHARACTERS[usize::from(SEVEN_BIT_CHARACTERS + eight_bits - FIRST_EIGHT_BIT_CODEWORD)],
+			);
+			reader.position += 8;
+			continue;
+		}
+
+		let code_point = reader.peek(15) - FIRST_FIFTEEN_BIT_CODEWORD + 0x80;
+		output.push(0b1100_0000 | (code_point >> 6) as u8);
+		output.push(0b1000_0000 | (code_point & 0b0011_1111) as u8);
+		reader.position += 15;
+	}
+
+	output
+}
+
+/// Decodes the first `length` bytes of data produced by [`dense_encode`].
+pub fn dense_decode(data: &[u8], length: usize) -> Result<Vec<u8>, Error> {
+	let mut codewords = [None; 128];
+	for (index, &character) in ASCII_CHARACTERS.iter().enumerate() {
+		let index = index as u16;
+		codewords[usize::from(character)] = Some(if index < SEVEN_BIT_CHARACTERS {
+			(index, 7)
+		} else {
+			(FIRST_EIGHT_BIT_CODEWORD + index - SEVEN_BIT_CHARACTERS, 8)
+		});
+	}
+
+	// length comes from the script, so it is capped by what data can decode into (every byte holds at most 8 bits)
+	let mut output = Vec::with_capacity(length.min(data.len().saturating_mul(2)));
+	let mut bits: u32 = 0;
+	let mut bit_count = 0;
+	let mut offset = 0;
+
+	while output.len() < length {
+		let first_byte = *data.get(offset).ok_or(Error::EndOfStream)?;
+
+		let (codeword, codeword_length) = if first_byte < 0x80 {
+			offset += 1;
+			codewords[usize::from(first_byte)].ok_or(Error::MalformedCharacter { offset: offset - 1 })?
+		} else {
+			// U+0080 to U+07FF are 0b110xxxxx 0b10xxxxxx, and lead bytes 0xC0 and 0xC1 would be overlong
+			match data.get(offset + 1) {
+				Some(&second_byte)
+					if (0xC2..0xE0).contains(&first_byte) && second_byte & 0b1100_0000 == 0b1000_0000 =>
+				{
+					offset += 2;
+					let code_point =
+						(u16::from(first_byte & 0b0001_1111) << 6) | u16::from(second_byte & 0b0011_1111);
+					(FIRST_FIFTEEN_BIT_CODEWORD + code_point - 0x80, 15)
+				}
+				_ => return Err(Error::MalformedCharacter { offset }),
+			}
+		};
+
+		bits = (bits << codeword_length) | u32::from(codeword);
+		bit_count += codeword_length;
+
+		while bit_count >= 8 && output.len() < length {
+			bit_count -= 8;
+			output.push((bits >> bit_count) as u8);
+		}
+
+		bits &= (1 << bit_count) - 1;
+	}
+
+	Ok(output)
+}
+
+/// Returns the lowest long bracket level whose closing long bracket doesn't appear in (or right after) `encoded`.
+/// Level 0 is also skipped if `encoded` contains `[[`, as Lua 5.1 rejects nested long brackets.
+#[must_use]
+pub fn long_bracket_level(encoded: &[u8]) -> usize {
+	(0..)
+		.find(|&level| {
+			if level == 0 && encoded.windows(2).any(|window| window == b"[[") {
+				return false;
+			}
+
+			let mut closing = vec![b']'];
+			closing.extend(std::iter::repeat_n(b'=', level));
+			closing.push(b']');
+
+			// data ending with "]=" would otherwise close "]=]" early
+			let mut source = encoded.to_vec();
+			source.extend_from_slice(&closing);
+			source
+				.windows(closing.len())
+				.position(|window| window == closing.as_slice())
+				== Some(encoded.len())
+		})
+		.expect("some long bracket level doesn't appear in the data")
+}
+
+/// Writes `data` (which must be from [`dense_encode`]) as a long bracket string, such as `[=[\n...]=]`.
+/// The newline after the opening long bracket is skipped by Lua, so data starting with a newline is kept intact.
+pub fn write_long_bracket_string(
+	encoded: &[u8],
+	writer: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+	let level = "=".repeat(long_bracket_level(encoded));
+
+	writer.write_all(format!("[{level}[\n").as_bytes())?;
+	writer.write_all(encoded)?;
+	writer.write_all(format!("]{level}]").as_bytes())
+}
+
+/// Generates the Luau decoder for [`dense_encode`]: a function named `f`, which takes the contents of a long bracket
+/// string and the length of the data, and returns a buffer.
+#[must_use]
+pub fn generate_luau_decoder() -> String {
+	format!(
+		"local a,b,c,d=table.freeze({{{}}}),{SEVEN_BIT_CHARACTERS},{FIRST_EIGHT_BIT_CODEWORD},{FIRST_FIFTEEN_BIT_CODEWORD} {}",
+		ASCII_CHARACTERS
+			.iter()
+			.map(u8::to_string)
+			.collect::<Vec<_>>()
+			.join(","),
+		include_str!("./luau/minifiedDenseDecoder.luau")
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Deterministic xorshift generator, so failures are reproducible.
+	struct Random(u64);
+
+	impl Random {
+		fn next(&mut self) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0
+		}
+	}
+
+	#[test]
+	fn codewords_are_complete() {
+		// every bit stream must parse into codewords
+		assert_eq!(FIRST_FIFTEEN_BIT_CODEWORD + (0x800 - 0x80), 1 << 15);
+	}
+
+	#[test]
+	fn round_trips() {
+		let mut random = Random(0x9E37_79B9_7F4A_7C15);
+
+		for length in 0..2000 {
+			let data: Vec<u8> = (0..length).map(|_| random.next() as u8).collect();
+			let encoded = dense_encode(&data);
+
+			let text = std::str::from_utf8(&encoded).expect("encoded data must be valid UTF-8");
+			assert!(!text.contains(['\0', '\r']));
+			assert_eq!(dense_decode(&encoded, data.len()), Ok(data));
+		}
+
+		for data in [vec![0; 100], vec![255; 100]] {
+			assert_eq!(dense_decode(&dense_encode(&data), data.len()), Ok(data));
+		}
+	}
+
+	#[test]
+	fn denser_than_base123() {
+		let mut random = Random(0x2545_F491_4F6C_DD1D);
+		let data: Vec<u8> = (0..100_000).map(|_| random.next() as u8).collect();
+
+		let dense = dense_encode(&data).len();
+		let base123 = crate::base122::base123_encode(&data).len();
+		assert!(dense < base123, "{dense} >= {base123}");
+	}
+
+	#[test]
+	fn decode_rejects_malformed_data() {
+		assert_eq!(
+			dense_decode(b"a\rb", 2),
+			Err(Error::MalformedCharacter { offset: 1 })
+		);
+		assert_eq!(
+			dense_decode(&[b'a', 0xC2], 2),
+			Err(Error::MalformedCharacter { offset: 1 })
+		);
+		assert_eq!(
+			dense_decode(&[0xC0, 0x80], 1),
+			Err(Error::MalformedCharacter { offset: 0 })
+		);
+		assert_eq!(dense_decode(b"a", 2), Err(Error::EndOfStream));
+		assert_eq!(dense_decode(b"a", usize::MAX), Err(Error::EndOfStream));
+	}
+
+	#[test]
+	fn picks_long_bracket_levels() {
+		assert_eq!(long_bracket_level(b"abc"), 0);
+		assert_eq!(long_bracket_level(b"a]]b"), 1);
+		assert_eq!(long_bracket_level(b"a[[b"), 1);
+		assert_eq!(long_bracket_level(b"]]]=]"), 2);
+		// "]=" followed by "]=]" closes early
+		assert_eq!(long_bracket_level(b"[[]="), 2);
+	}
+}
@@ -0,0 +1,426 @@
+//! Azalea's patch logic
+//!
+//! A patch updates a tree which was decoded earlier (from an old model) into a new model in place, see [`PatchRecordKind`].
+//! Instances of the new model are matched to the instances of the old model they update by their `UniqueId`, and then by their path.
+//!
+//! Some changes can't be made in place, so the instance (and its subtree) is replaced instead:
+//! - its ClassName changed
+//! - it is a script whose Source changed, as scripts can't change their Source at runtime
+//! - it no longer has some of its properties (or one of its referents became nil), as properties can't be unset
+//!
+//! Models which can't be patched at all (their root lost a property, or a referent points outside of the new root) fail to diff,
+//! and need a full payload instead.
+
+use crate::{
+	emit::{Options, Requirements},
+	encoder::{
+		BlobTable, PropertyOverrides, StringTable, add_type_id_requirements, encode_instance,
+		encode_property, variant_referent, write_header, write_interned_string, write_string_variant,
+		write_variant,
+	},
+	spec::{FormatFlags, PATCH_MAGIC, PatchRecordKind, TypeId},
+};
+use color_eyre::eyre::{self, WrapErr, bail, ensure};
+use rbx_dom_weak::{
+	Instance, WeakDom,
+	types::{Ref, UniqueId, Variant},
+};
+use std::{
+	collections::{HashMap, HashSet},
+	io::Write,
+};
+
+/// Classes which can't change their Source once they're created.
+const SCRIPT_CLASSES: [&str; 3] = ["Script", "LocalScript", "ModuleScript"];
+
+/// Requirements which control the runtime a patch is applied in, every other requirement is ignored by [`diff_doms_into_writer`].
+const RUNTIME_REQUIREMENTS: Requirements = Requirements::STUDIO_SUPPORT
+	.union(Requirements::OPENSB_SUPPORT)
+	.union(Requirements::LEGACY_SUPPORT)
+	.union(Requirements::ZSTD_FALLBACK)
+	.union(Requirements::PROPERTY_ERROR_REPORT);
+
+/// Returns the only top-level instance of a model, which patches are relative to.
+pub(crate) fn model_root(weak_dom: &WeakDom) -> eyre::Result<Ref> {
+	match weak_dom.root().children() {
+		[root] => Ok(*root),
+		children => bail!(
+			"patches need models with exactly one top-level instance, but this model has {}",
+			children.len()
+		),
+	}
+}
+
+/// Returns whether `new` still has every property of `old`, as patches can't unset properties (or clear referents).
+fn keeps_properties(old: &Instance, new: &Instance) -> bool {
+	old
+		.properties
+		.iter()
+		.all(|(property, value)| match new.properties.get(property) {
+			Some(new_value) => variant_referent(value).is_none() || variant_referent(new_value).is_some(),
+			None => false,
+		})
+}
+
+/// Returns whether `new` can update `old` in place, see the module documentation.
+fn updatable(old: &Instance, new: &Instance) -> bool {
+	old.class == new.class
+		&& (!SCRIPT_CLASSES.contains(&new.class.as_str())
+			|| old.properties.get(&"Source".into()) == new.properties.get(&"Source".into()))
+		&& keeps_properties(old, new)
+}
+
+/// Returns the index of `instance` among the children of its parent which share its name.
+fn sibling_index(weak_dom: &WeakDom, instance: &Instance) -> usize {
+	weak_dom
+		.get_by_ref(instance.parent())
+		.unwrap()
+		.children()
+		.iter()
+		.take_while(|sibling| **sibling != instance.referent())
+		.filter(|sibling| weak_dom.get_by_ref(**sibling).unwrap().name == instance.name)
+		.count()
+}
+
+/// Returns the path of `referent` below `root`, see [`PatchRecordKind::Locate`].
+fn instance_path(weak_dom: &WeakDom, root: Ref, mut referent: Ref) -> Vec<(&str, usize)> {
+	let mut path = Vec::new();
+
+	while referent != root {
+		let instance = weak_dom.get_by_ref(referent).unwrap();
+		path.push((instance.name.as_str(), sibling_index(weak_dom, instance)));
+		referent = instance.parent();
+	}
+
+	path.reverse();
+	path
+}
+
+/// Returns the instances below `root` by their `UniqueId`, leaving out ids which aren't unique.
+fn unique_ids(weak_dom: &WeakDom, root: Ref) -> HashMap<UniqueId, Ref> {
+	let mut referents: HashMap<UniqueId, Option<Ref>> = HashMap::new();
+
+	for instance in weak_dom.descendants_of(root) {
+		if let Some(Variant::UniqueId(id)) = instance.properties.get(&"UniqueId".into()) {
+			referents
+				.entry(*id)
+				.and_modify(|referent| *referent = None)
+				.or_insert(Some(instance.referent()));
+		}
+	}
+
+	referents
+		.into_iter()
+		.filter_map(|(id, referent)| Some((id, referent?)))
+		.collect()
+}
+
+/// Matches instances of `new` (below `new_root`) to the instances of `old` (below `old_root`) they update, see the module documentation.
+fn match_instances(
+	old: &WeakDom,
+	old_root: Ref,
+	new: &WeakDom,
+	new_root: Ref,
+) -> HashMap<Ref, Ref> {
+	let mut matches = HashMap::from([(new_root, old_root)]);
+	let mut matched = HashSet::from([old_root]);
+
+	let old_ids = unique_ids(old, old_root);
+	for (id, referent) in unique_ids(new, new_root) {
+		if let Some(&old_referent) = old_ids.get(&id)
+			&& referent != new_root
+			&& old_referent != old_root
+			&& updatable(
+				old.get_by_ref(old_referent).unwrap(),
+				new.get_by_ref(referent).unwrap(),
+			) {
+			matches.insert(referent, old_referent);
+			matched.insert(old_referent);
+		}
+	}
+
+	// the remaining children of matched instances are matched by their name and index among siblings of that name
+	let mut stack = vec![new_root];
+	while let Some(referent) = stack.pop() {
+		let instance = new.get_by_ref(referent).unwrap();
+		stack.extend(instance.children().iter().rev().copied());
+
+		let Some(&old_referent) = matches.get(&referent) else {
+			continue;
+		};
+
+		let mut old_children = HashMap::new();
+		for &old_child in old.get_by_ref(old_referent).unwrap().children() {
+			let old_child = old.get_by_ref(old_child).unwrap();
+			old_children.insert(
+				(old_child.name.as_str(), sibling_index(old, old_child)),
+				old_child,
+			);
+		}
+
+		for &child in instance.children() {
+			let child = new.get_by_ref(child).unwrap();
+			if matches.contains_key(&child.referent()) {
+				continue;
+			}
+
+			if let Some(old_child) = old_children.get(&(child.name.as_str(), sibling_index(new, child)))
+				&& !matched.contains(&old_child.referent())
+				&& updatable(old_child, child)
+			{
+				matches.insert(child.referent(), old_child.referent());
+				matched.insert(old_child.referent());
+			}
+		}
+	}
+
+	matches
+}
+
+/// Returns whether `old` (a value in the old tree) equals `new`, where referents are compared through `old_referents`.
+fn values_equal(old: &Variant, new: &Variant, old_referents: &HashMap<Ref, Ref>) -> bool {
+	match (variant_referent(old), variant_referent(new)) {
+		(Some(old_target), Some(new_target)) => {
+			std::mem::discriminant(old) == std::mem::discriminant(new)
+				&& old_referents.get(&old_target) == Some(&new_target)
+		}
+		(None, None) => old == new,
+		_ => false,
+	}
+}
+
+/// Writes a [`PatchRecordKind::Change`] record for the name and properties of `new` which differ from `old`, if there are any.
+fn encode_changes<'dom>(
+	old: &Instance,
+	new: &'dom Instance,
+	old_referents: &HashMap<Ref, Ref>,
+	options: &mut Options<'dom>,
+	buffer: &mut impl Write,
+) -> eyre::Result<()> {
+	let renamed = old.name != new.name;
+	let changed = new
+		.properties
+		.iter()
+		.filter(|(property, value)| {
+			*property != "UniqueId"
+				&& !old
+					.properties
+					.get(*property)
+					.is_some_and(|old_value| values_equal(old_value, value, old_referents))
+		})
+		.collect::<Vec<_>>();
+
+	if !renamed && changed.is_empty() {
+		return Ok(());
+	}
+
+	buffer
+		.write_all(&[PatchRecordKind::Change as u8])
+		.wrap_err("failed writing patch record kind")?;
+	write_variant(
+		buffer,
+		&Variant::Ref(new.referent()),
+		&mut options.referent_map,
+		&mut options.string_table,
+	)?;
+	buffer.write_all(
+		&(u16::try_from(changed.len() + usize::from(renamed))
+			.wrap_err("failed truncating changed properties length to u16")?)
+		.to_le_bytes(),
+	)?;
+
+	if renamed {
+		write_interned_string(buffer, "Name", &mut options.string_table)
+			.wrap_err("failed writing Name as interned string")?;
+		write_string_variant(buffer, &new.name)?;
+	}
+
+	for (property, value) in changed {
+		write_interned_string(buffer, property, &mut options.string_table)
+			.wrap_err("failed writing property name as interned string")?;
+		encode_property(new, property, value, options, buffer)
+			.wrap_err_with(|| format!("failed encoding changed property {property}"))?;
+	}
+
+	Ok(())
+}
+
+/// Writes a patch which updates a tree decoded from `old` into `new` into a writer that implements the [`Write`] trait.
+///
+/// Both models must have exactly one top-level instance (of the same class), which patches are applied to.
+/// Only the runtime requirements of `base_requirements` (such as [`Requirements::OPENSB_SUPPORT`]) are used, and the returned
+/// [`Options`] always contain [`Requirements::PATCH_SUPPORT`].
+pub fn diff_doms_into_writer<'dom>(
+	old: &WeakDom,
+	new: &'dom WeakDom,
+	mut writer: impl Write,
+	base_requirements: Requirements,
+) -> eyre::Result<Options<'dom>> {
+	let old_root = model_root(old).wrap_err("failed finding root of old model")?;
+	let new_root = model_root(new).wrap_err("failed finding root of new model")?;
+	ensure!(
+		old.get_by_ref(old_root).unwrap().class == new.get_by_ref(new_root).unwrap().class,
+		"the root of both models must have the same class"
+	);
+	ensure!(
+		keeps_properties(
+			old.get_by_ref(old_root).unwrap(),
+			new.get_by_ref(new_root).unwrap()
+		),
+		"the root of the new model lost properties of the old root, which patches can't unset"
+	);
+
+	// patches can only locate (or add) instances below the root, so referents can't point anywhere else
+	let new_referents = new
+		.descendants_of(new_root)
+		.map(Instance::referent)
+		.collect::<HashSet<_>>();
+	for instance in new.descendants_of(new_root) {
+		for (property, value) in &instance.properties {
+			if let Some(target) = variant_referent(value) {
+				ensure!(
+					new_referents.contains(&target),
+					"{}.{property} points outside of the model root, which patches can't reference",
+					instance.name
+				);
+			}
+		}
+	}
+
+	let matches = match_instances(old, old_root, new, new_root);
+	let old_referents = matches
+		.iter()
+		.map(|(referent, old_referent)| (*old_referent, *referent))
+		.collect::<HashMap<_, _>>();
+
+	let mut options = Options {
+		generation_requirements: base_requirements.intersection(RUNTIME_REQUIREMENTS)
+			| Requirements::PATCH_SUPPORT,
+		format_flags: FormatFlags::empty(),
+		known_needed_type_ids: HashSet::from([TypeId::String, TypeId::Ref, TypeId::None]),
+		module_script_sources: HashMap::new(),
+		referent_map: HashMap::new(),
+		string_table: StringTable::default(),
+		schemas: None,
+		blob_table: BlobTable::default(),
+		property_overrides: PropertyOverrides::default(),
+		unwritable_properties: Vec::new(),
+	};
+
+	// parents are added (or moved) before their children, so every new parent exists by the time it is used
+	let mut records = Vec::new();
+	let mut stack = vec![new_root];
+	while let Some(referent) = stack.pop() {
+		let instance = new.get_by_ref(referent).unwrap();
+		stack.extend(instance.children().iter().rev().copied());
+
+		let Some(&old_referent) = matches.get(&referent) else {
+			records
+				.write_all(&[PatchRecordKind::Add as u8])
+				.wrap_err("failed writing patch record kind")?;
+			encode_instance(instance, &mut options, &mut records)
+				.wrap_err_with(|| format!("failed encoding added instance {}", instance.name))?;
+			continue;
+		};
+
+		let old_instance = old.get_by_ref(old_referent).unwrap();
+		if referent != new_root && old_referents.get(&old_instance.parent()) != Some(&instance.parent())
+		{
+			records
+				.write_all(&[PatchRecordKind::Reparent as u8])
+				.wrap_err("failed writing patch record kind")?;
+
+			for referent in [referent, instance.parent()] {
+				write_variant(
+					&mut records,
+					&Variant::Ref(referent),
+					&mut options.referent_map,
+					&mut options.string_table,
+				)?;
+			}
+		}
+
+		encode_changes(
+			old_instance,
+			instance,
+			&old_referents,
+			&mut options,
+			&mut records,
+		)
+		.wrap_err_with(|| format!("failed encoding changes of {}", instance.name))?;
+	}
+
+	// instances are removed last, as their descendants may have been moved elsewhere
+	let mut stack = vec![old_root];
+	while let Some(old_referent) = stack.pop() {
+		if !old_referents.contains_key(&old_referent) {
+			records
+				.write_all(&[PatchRecordKind::Remove as u8])
+				.wrap_err("failed writing patch record kind")?;
+			write_variant(
+				&mut records,
+				&Variant::Ref(old_referent),
+				&mut options.referent_map,
+				&mut options.string_table,
+			)?;
+			continue;
+		}
+
+		stack.extend(old.get_by_ref(old_referent).unwrap().children());
+	}
+
+	// every instance which existed before the patch (matched instances of new, and removed instances of old) is located by its path
+	let mut located = options
+		.referent_map
+		.iter()
+		.filter_map(|(referent, index)| {
+			let old_referent = matches
+				.get(referent)
+				.copied()
+				.or_else(|| old.get_by_ref(*referent).map(|_| *referent))?;
+
+			Some((*index, *referent, old_referent))
+		})
+		.collect::<Vec<_>>();
+	located.sort_unstable_by_key(|(index, ..)| *index);
+
+	let mut locates = Vec::new();
+	for (_, referent, old_referent) in located {
+		locates
+			.write_all(&[PatchRecordKind::Locate as u8])
+			.wrap_err("failed writing patch record kind")?;
+		write_variant(
+			&mut locates,
+			&Variant::Ref(referent),
+			&mut options.referent_map,
+			&mut options.string_table,
+		)?;
+
+		let path = instance_path(old, old_root, old_referent);
+		leb128::write::unsigned(&mut locates, path.len().try_into()?)
+			.wrap_err("failed writing path length as leb128 encoded unsigned integer")?;
+
+		for (name, index) in path {
+			write_interned_string(&mut locates, name, &mut options.string_table)
+				.wrap_err("failed writing path name as interned string")?;
+			leb128::write::unsigned(&mut locates, index.try_into()?)
+				.wrap_err("failed writing sibling index as leb128 encoded unsigned integer")?;
+		}
+	}
+
+	write_header(writer.by_ref(), PATCH_MAGIC, options.format_flags)?;
+	options
+		.string_table
+		.write_into(writer.by_ref())
+		.wrap_err("failed writing string table")?;
+	writer
+		.write_all(&locates)
+		.wrap_err("failed writing locate records")?;
+	writer
+		.write_all(&records)
+		.wrap_err("failed writing patch records")?;
+
+	add_type_id_requirements(&mut options);
+
+	Ok(options)
+}
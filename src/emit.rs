@@ -6,14 +6,21 @@ use rbx_dom_weak::types::Ref;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
 
-use crate::spec::{ALL_TYPE_IDS, TypeId, get_luau_for_type_ids, get_luau_variant_decoder_for_ids};
+#[cfg(feature = "base122")]
+use crate::base122::Alphabet;
+
+use crate::spec::{
+	ALL_TYPE_IDS, ColumnKind, FORMAT_MAGIC, FORMAT_VERSION, FormatFlags, HEADER_LENGTH, PATCH_MAGIC,
+	PatchRecordKind, RecordKind, TypeId, get_luau_column_word_counts, get_luau_for_type_ids,
+	get_luau_variant_decoder_for_ids,
+};
 
 bitflags::bitflags! {
 	/// Implicit requirements can be set by consumers of the crate, but they will usually be automatically generated.
 	/// Instead, set explicit requirements, as they mainly control the behavior of the generated code, and are not automatically generated.
 	// #[repr(transparent)]
 	#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-	pub struct Requirements: u16 {
+	pub struct Requirements: u32 {
 		/// Enable this if you want to decode anything which references a CFrame value.
 		///
 		/// This is an IMPLICIT requirement.
@@ -71,21 +78,166 @@ bitflags::bitflags! {
 		const RETURN_DECODE = 512;
 
 		/// Enable this to properly decode instances with Content property values that reference other objects (also known as referents/instances).
-		/// 
+		///
 		/// Related to `Content.fromObject(...)` decoding support.
 		///
 		/// This is an IMPLICIT requirement.
 		const CONTENT_OBJECT_SUPPORT = 1024;
+
+		/// Writes the property names of every class once, and only encodes property values in each instance.
+		///
+		/// Cuts payload size and decode time for models with many instances of the same class (such as UI libraries).
+		///
+		/// This is an EXPLICIT requirement.
+		const PROPERTY_SCHEMAS = 2048;
+
+		/// Groups property values by property across every instance of a class (like rbxm chunks), instead of by instance.
+		/// Values which are only made of f32s and i32s (such as Vector3) are further split so their components sit together.
+		///
+		/// Implies [`Self::PROPERTY_SCHEMAS`]. Usually compresses better, especially for models with many parts or UI objects.
+		///
+		/// This is an EXPLICIT requirement.
+		const COLUMNAR_LAYOUT = 4096;
+
+		/// Shuffles the bytes of transposed [`Self::COLUMNAR_LAYOUT`] columns, so that e.g. the exponents of similar floats sit together.
+		///
+		/// This is an EXPLICIT requirement.
+		const BYTE_SHUFFLE = 8192;
+
+		/// Leaves properties which equal their reflection database default out of the payload, as `Instance.new` already sets them.
+		///
+		/// Environments which change defaults should use [`crate::encoder::encode_dom_into_writer_with_defaults`] instead.
+		///
+		/// This is an EXPLICIT requirement.
+		const DROP_DEFAULT_PROPERTIES = 16384;
+
+		/// Leaves properties which scripts can't write (such as `Capabilities` or `PhysicsData`) out of the payload, as decoders would fail setting them.
+		/// Serialized-only aliases of writable properties (such as `Fire.heat_xml`) are encoded as the property they alias instead.
+		///
+		/// Every skipped property is reported in [`Options::unwritable_properties`].
+		///
+		/// This is an EXPLICIT requirement.
+		const SKIP_UNWRITABLE_PROPERTIES = 32768;
+
+		/// Encodes repeats of a subtree (ignoring the name of its root) as a record which `:Clone()`s the first one, instead of encoding them again.
+		/// Subtrees which contain scripts or instances which aren't `Archivable` are always encoded.
+		///
+		/// This is an EXPLICIT requirement.
+		const DEDUPLICATE_SUBTREES = 65536;
+
+		/// Writes large strings and BinaryStrings (such as the Source of vendored packages, or SharedStrings) which appear more than once
+		/// into a table keyed by their contents, which properties reference instead of repeating them. Decoders read them on first use.
+		///
+		/// This is an EXPLICIT requirement.
+		const BLOB_TABLE = 131072;
+
+		/// Emits an `applyPatch(root, payloadBuffer)` function, which updates a tree decoded earlier with a patch from [`crate::diff`].
+		///
+		/// This is an EXPLICIT requirement.
+		const PATCH_SUPPORT = 262144;
+
+		/// Embeds a pure-Luau zstd decompressor, which generated scripts use to decompress their payload when `EncodingService` is unavailable
+		/// (such as in sandboxes, older clients and Luau runtimes outside of Roblox).
+		///
+		/// This is an EXPLICIT requirement.
+		const ZSTD_FALLBACK = 524288;
+
+		/// Enable this if you want to decompress payloads compressed with [`Compression::Lz`].
+		///
+		/// This is an IMPLICIT requirement.
+		const LZ_DECOMPRESSOR = 1048576;
+
+		/// Emits a `decodeAsync(payloadBuffer, options)` function, which decodes in time slices and yields (with `task.wait`)
+		/// after a budget of instances or milliseconds, so huge models don't time out the script decoding them.
+		/// It reports progress through an optional callback, and returns a handle which can be awaited, or called back on completion.
+		///
+		/// Generated scripts await `decodeAsync` instead of calling `decode`.
+		///
+		/// This is an EXPLICIT requirement.
+		const YIELDING_DECODER = 2097152;
+
+		/// Collects properties which fail to apply (the instance's full name, the property, the value's type and the error)
+		/// into a report, which `decode` returns next to the root. Without this, failures are only passed to `onPropertyError`.
+		/// `decode` warns a summary of the report when its `printPropertyErrors` option is set, which generated scripts set.
+		///
+		/// Meant for debugging models which decode incorrectly, leave it out of release builds so they stay silent.
+		///
+		/// This is an EXPLICIT requirement.
+		const PROPERTY_ERROR_REPORT = 4194304;
+	}
+}
+
+/// How generated scripts compress their embedded payload, which also decides how they decompress it.
+#[cfg(feature = "base122")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression<'dictionary> {
+	/// Embeds the payload as is, for tiny models where the decompress call costs more than it saves.
+	None,
+	/// Zstandard, decompressed by `EncodingService` (see also [`Requirements::ZSTD_FALLBACK`]).
+	Zstd(ZstdParameters),
+	/// Zstandard with a (usually trained) dictionary, which small payloads compress much better with.
+	///
+	/// Scripts decompress their payload with the dictionary runtime from [`generate_dictionary_runtime`], which must run
+	/// before them. Models which share a vocabulary share a dictionary (and its runtime) this way.
+	ZstdDictionary {
+		parameters: ZstdParameters,
+		dictionary: &'dictionary [u8],
+	},
+	/// LZ4 frames, decompressed by `EncodingService`. Compresses worse than zstd, but decompresses faster.
+	Lz4,
+	/// LZ4 blocks prefixed with their decompressed length (as a u32), decompressed by a small Luau decompressor
+	/// embedded into the script, for environments without any native codec.
+	Lz,
+}
+
+#[cfg(feature = "base122")]
+impl Default for Compression<'_> {
+	fn default() -> Self {
+		Self::Zstd(ZstdParameters::level(11))
+	}
+}
+
+/// Parameters for [`Compression::Zstd`] and [`Compression::ZstdDictionary`].
+#[cfg(feature = "base122")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZstdParameters {
+	/// Compression level, 1 to 22 (slowest).
+	pub level: u8,
+	/// Finds matches much further back than usual, such as between copies of a large vendored package.
+	pub long_distance_matching: bool,
+	/// Log2 of the window size (at most 27, which decoders accept by default), or `None` for the level's default.
+	pub window_log: Option<u32>,
+}
+
+#[cfg(feature = "base122")]
+impl ZstdParameters {
+	/// The default parameters of a compression level.
+	#[must_use]
+	pub const fn level(level: u8) -> Self {
+		Self {
+			level,
+			long_distance_matching: false,
+			window_log: None,
+		}
 	}
 }
 
 pub struct Options<'options> {
 	pub generation_requirements: Requirements,
+	/// The payload was encoded with these flags, generated decoders refuse payloads with any other flags.
+	pub format_flags: FormatFlags,
 
 	// i don't want consumers of azalea to accidentally break stuff
 	pub(crate) known_needed_type_ids: HashSet<TypeId>,
 	pub(crate) module_script_sources: HashMap<usize, &'options str>,
 	pub(crate) referent_map: HashMap<Ref, usize>,
+	pub(crate) string_table: crate::encoder::StringTable,
+	pub(crate) schemas: Option<crate::encoder::SchemaTable>,
+	pub(crate) blob_table: crate::encoder::BlobTable<'options>,
+	/// Properties which are left out of the payload or renamed, see [`Requirements::DROP_DEFAULT_PROPERTIES`] and [`Requirements::SKIP_UNWRITABLE_PROPERTIES`].
+	pub(crate) property_overrides: crate::encoder::PropertyOverrides,
+	/// Properties which were left out of the payload (or renamed) because scripts can't write them, see [`Requirements::SKIP_UNWRITABLE_PROPERTIES`].
+	pub unwritable_properties: Vec<crate::encoder::UnwritableProperty>,
 }
 
 #[derive(Template)]
@@ -97,6 +249,26 @@ struct DecoderTemplate<'template> {
 	new_module_script_shim: Option<&'template str>,
 	variant_decoder_table: &'template str,
 
+	format_magic: &'template str,
+	format_version: u8,
+	supported_format_flags: u16,
+	property_schemas_flag: u16,
+	columnar_layout_flag: u16,
+	variants_column_kind: u8,
+	shuffled_words_column_kind: u8,
+	column_word_counts: &'template str,
+	cloned_subtrees_flag: u16,
+	clone_record_kind: u8,
+	blob_table_flag: u16,
+	patch_magic: &'template str,
+	locate_patch_record_kind: u8,
+	add_patch_record_kind: u8,
+	change_patch_record_kind: u8,
+	reparent_patch_record_kind: u8,
+	remove_patch_record_kind: u8,
+	header_length: usize,
+	zstd_decompressor: &'template str,
+
 	requirements: Requirements,
 }
 
@@ -242,6 +414,25 @@ pub fn generate_with_options(options: &Options) -> String {
 		new_local_script_shim: new_local_script_shim.as_deref(),
 		new_module_script_shim: new_module_script_shim.as_deref(),
 		variant_decoder_table: &get_luau_variant_decoder_for_ids(type_ids.iter()),
+		format_magic: std::str::from_utf8(&FORMAT_MAGIC).unwrap(),
+		format_version: FORMAT_VERSION,
+		supported_format_flags: options.format_flags.bits(),
+		property_schemas_flag: FormatFlags::PROPERTY_SCHEMAS.bits(),
+		columnar_layout_flag: FormatFlags::COLUMNAR_LAYOUT.bits(),
+		variants_column_kind: ColumnKind::Variants as u8,
+		shuffled_words_column_kind: ColumnKind::ShuffledWords as u8,
+		column_word_counts: &get_luau_column_word_counts(),
+		cloned_subtrees_flag: FormatFlags::CLONED_SUBTREES.bits(),
+		clone_record_kind: RecordKind::Clone as u8,
+		blob_table_flag: FormatFlags::BLOB_TABLE.bits(),
+		patch_magic: std::str::from_utf8(&PATCH_MAGIC).unwrap(),
+		locate_patch_record_kind: PatchRecordKind::Locate as u8,
+		add_patch_record_kind: PatchRecordKind::Add as u8,
+		change_patch_record_kind: PatchRecordKind::Change as u8,
+		reparent_patch_record_kind: PatchRecordKind::Reparent as u8,
+		remove_patch_record_kind: PatchRecordKind::Remove as u8,
+		header_length: HEADER_LENGTH,
+		zstd_decompressor: include_str!("./luau/zstd.luau"),
 		requirements,
 	};
 
@@ -254,56 +445,167 @@ pub fn generate_with_options(options: &Options) -> String {
 /// Notably, models generated with [`Requirements::USE_NOVEL_INLINING`] exclude the Source property.
 #[must_use]
 pub fn generate_full_decoder() -> String {
-	generate_with_options(&Options {
-		generation_requirements: Requirements::all().difference(Requirements::USE_NOVEL_INLINING),
+	generate_full_decoder_with(Requirements::empty())
+}
+
+/// Like [`generate_full_decoder`], but with the opt-in decoder features in `extra_requirements`:
+/// [`Requirements::YIELDING_DECODER`] (which makes the decoder hold `decodeAsync` as well) and [`Requirements::PROPERTY_ERROR_REPORT`].
+/// Other requirements are ignored, as the full decoder already has every other one it can use.
+#[must_use]
+pub fn generate_full_decoder_with(extra_requirements: Requirements) -> String {
+	let full_decoder_requirements = Requirements::all().difference(
+		Requirements::USE_NOVEL_INLINING
+			| Requirements::ZSTD_FALLBACK
+			| Requirements::LZ_DECOMPRESSOR
+			| Requirements::YIELDING_DECODER
+			| Requirements::PROPERTY_ERROR_REPORT,
+	);
+
+	generate_with_options(&full_decoder_options(
+		full_decoder_requirements
+			| extra_requirements
+				.intersection(Requirements::YIELDING_DECODER | Requirements::PROPERTY_ERROR_REPORT),
+	))
+}
+
+/// [`Options`] which can decode any payload (besides ones using novel inlining), and patches.
+fn full_decoder_options<'a>(generation_requirements: Requirements) -> Options<'a> {
+	Options {
+		generation_requirements,
+		format_flags: FormatFlags::all().difference(FormatFlags::NOVEL_INLINING),
 		known_needed_type_ids: HashSet::from(ALL_TYPE_IDS),
 		module_script_sources: HashMap::new(),
 		referent_map: HashMap::new(),
-	})
+		string_table: crate::encoder::StringTable::default(),
+		schemas: None,
+		blob_table: crate::encoder::BlobTable::default(),
+		property_overrides: crate::encoder::PropertyOverrides::default(),
+		unwritable_properties: Vec::new(),
+	}
 }
 
 #[cfg(feature = "base122")]
-fn internal_create_script(
-	weak_dom: &WeakDom,
+fn internal_create_script<'dom>(
+	weak_dom: &'dom WeakDom,
 	base_requirements: Requirements,
-	level: u8,
+	compression: Compression,
+	payload_encoding: PayloadEncoding,
 
 	writer: &mut impl std::io::Write,
-) {
+) -> Options<'dom> {
 	/*
 		* in a perfect world, we would be able to directly wrap writers around each other as below:
-		* [[azalea encoder] -> [zstd writer] -> [base64/base122 writer]]
+		* [[azalea encoder] -> [zstd writer] -> [base122 writer]]
 		* all steps above would be perfectly piped
 		*
 		* however, roblox expects the zstd output to have a pledged src size, however
 		* it is impossible to figure out the actual src content size without wasting resources (ram, cpu)
 		*
-		* currently, the chain would look something like this:
-		* [azalea encoder] -> [Vec<u8> (heap)] -> [[zstd writer] -> [base64/base122 writer]]
-		* however, the base122 writer is pretty unstable and results in corrupted blocks / checksum failures
+		* so the chain looks like this (see embed_payload):
+		* [azalea encoder] -> [Vec<u8> (heap)] -> [[zstd writer] -> [base122 writer] -> writer]
 		*/
 
-	// let mut output = String::new();
 	let mut encoded_dom = Vec::new();
 
-	let options =
+	let mut options =
 		crate::encoder::encode_dom_into_writer(weak_dom, &mut encoded_dom, base_requirements)
 			.expect("failed encoding dom");
 
-	let mut zstd_out = Vec::with_capacity(encoded_dom.len() / 2);
-	let mut zstd_encoder = zstd::Encoder::new(&mut zstd_out, i32::from(level)).unwrap();
-	zstd_encoder.include_checksum(true).unwrap();
-	zstd_encoder.include_contentsize(true).unwrap();
-	zstd_encoder
-		.set_pledged_src_size(Some(encoded_dom.len() as u64))
-		.unwrap();
+	embed_payload(
+		&mut options,
+		encoded_dom,
+		compression,
+		payload_encoding,
+		writer,
+	);
 
-	std::io::copy(&mut std::io::Cursor::new(encoded_dom), &mut zstd_encoder).unwrap();
-	zstd_encoder.finish().unwrap();
+	options
+}
+
+/// Compresses a payload (or patch) in the way its decompressor (see [`Compression`]) expects.
+#[cfg(feature = "base122")]
+pub(crate) fn compress_payload(encoded_dom: Vec<u8>, compression: Compression) -> Vec<u8> {
+	if compression == Compression::None {
+		return encoded_dom;
+	}
+
+	let mut compressed = Vec::with_capacity(encoded_dom.len() / 2);
+	compress_payload_into(&encoded_dom, compression, &mut compressed)
+		.expect("failed writing into memory buffer");
+
+	compressed
+}
+
+/// Like [`compress_payload`], but streams the compressed payload into `writer`.
+#[cfg(feature = "base122")]
+fn compress_payload_into(
+	encoded_dom: &[u8],
+	compression: Compression,
+	writer: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+	match compression {
+		Compression::None => writer.write_all(encoded_dom),
+		Compression::Zstd(parameters) => compress_zstd_into(encoded_dom, parameters, &[], writer),
+		Compression::ZstdDictionary {
+			parameters,
+			dictionary,
+		} => compress_zstd_into(encoded_dom, parameters, dictionary, writer),
+		Compression::Lz4 => {
+			let frame_info =
+				lz4_flex::frame::FrameInfo::new().content_size(Some(encoded_dom.len() as u64));
+			let mut lz4_encoder = lz4_flex::frame::FrameEncoder::with_frame_info(frame_info, writer);
+
+			std::io::Write::write_all(&mut lz4_encoder, encoded_dom)?;
+			lz4_encoder.finish().map_err(std::io::Error::other)?;
+
+			Ok(())
+		}
+		Compression::Lz => writer.write_all(&lz4_flex::block::compress_prepend_size(encoded_dom)),
+	}
+}
+
+/// Compresses with zstd into `writer`, `dictionary` may be empty.
+#[cfg(feature = "base122")]
+fn compress_zstd_into(
+	encoded_dom: &[u8],
+	parameters: ZstdParameters,
+	dictionary: &[u8],
+	writer: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+	let mut zstd_encoder =
+		zstd::Encoder::with_dictionary(writer, i32::from(parameters.level), dictionary)?;
+	zstd_encoder.include_checksum(true)?;
+	zstd_encoder.include_contentsize(true)?;
+	zstd_encoder.long_distance_matching(parameters.long_distance_matching)?;
+	if let Some(window_log) = parameters.window_log {
+		zstd_encoder.window_log(window_log)?;
+	}
+
+	// roblox expects zstd frames to have a pledged source size
+	zstd_encoder.set_pledged_src_size(Some(encoded_dom.len() as u64))?;
+
+	std::io::Write::write_all(&mut zstd_encoder, encoded_dom)?;
+	zstd_encoder.finish()?;
+
+	Ok(())
+}
+
+/// Writes a decoder generated from `options`, followed by the base decoder (`f`) for `payload_encoding`.
+/// Adds the requirements the chosen [`Compression`] needs to `options`.
+#[cfg(feature = "base122")]
+fn write_decoders(
+	options: &mut Options,
+	compression: Compression,
+	payload_encoding: PayloadEncoding,
+	writer: &mut impl std::io::Write,
+) {
+	if compression == Compression::Lz {
+		options.generation_requirements |= Requirements::LZ_DECOMPRESSOR;
+	}
 
 	// embed decoder
 	writer
-		.write_all(generate_with_options(&options).as_bytes())
+		.write_all(generate_with_options(options).as_bytes())
 		.expect("failed writing decoder src into writer");
 
 	// old base64 generator
@@ -311,63 +613,640 @@ fn internal_create_script(
 	// output.push_str(&BASE64_STANDARD.encode(&zstd_out));
 	// output.push_str("\"}]])\n");
 
+	let base_decoder = match payload_encoding {
+		PayloadEncoding::Base122(alphabet) => {
+			crate::base122::generate_luau_decoder(alphabet.illegal_bytes())
+		}
+		PayloadEncoding::Dense => crate::dense::generate_luau_decoder(),
+	};
 	writer
-		.write_all(include_bytes!("./luau/minifiedCombinator.luau"))
-		.expect("failed writing minified base123 decoder");
+		.write_all(base_decoder.as_bytes())
+		.expect("failed writing base decoder");
+}
 
+/// Returns what goes before and after the (still compressed) payload buffer to assign `payloadBuffer`.
+#[cfg(feature = "base122")]
+fn decompression_call(
+	options: &Options,
+	compression: Compression,
+) -> (std::borrow::Cow<'static, str>, &'static str) {
+	let zstd_fallback = options
+		.generation_requirements
+		.contains(Requirements::ZSTD_FALLBACK);
+
+	// compressed payloads are always passed to a DecompressBuffer method, which extraction relies on (see crate::extract)
+	match compression {
+		Compression::None => ("local payloadBuffer=".into(), ""),
+		Compression::Zstd(_) if zstd_fallback => (
+			"local payloadBuffer=ZstdDecompressor:DecompressBuffer(".into(),
+			")",
+		),
+		Compression::Zstd(_) => (
+			"local payloadBuffer=game:GetService('EncodingService'):DecompressBuffer(".into(),
+			",Enum.CompressionAlgorithm.Zstd)",
+		),
+		Compression::ZstdDictionary { dictionary, .. } => (
+			format!(
+				"local payloadBuffer=assert(shared.{DICTIONARY_RUNTIMES} and shared.{DICTIONARY_RUNTIMES}['{}'],'the dictionary runtime must run before this script'):DecompressBuffer(",
+				zstd_dictionary_key(dictionary)
+			)
+			.into(),
+			")",
+		),
+		Compression::Lz4 => (
+			"local payloadBuffer=game:GetService('EncodingService'):DecompressBuffer(".into(),
+			",Enum.CompressionAlgorithm.LZ4)",
+		),
+		Compression::Lz => ("local payloadBuffer=LzDecompressor:DecompressBuffer(".into(), ")"),
+	}
+}
+
+/// Writes the decoders (see [`write_decoders`]), followed by `payloadBuffer` (the compressed payload, encoded with `payload_encoding`).
+/// Adds the requirements the chosen [`Compression`] needs to `options`.
+#[cfg(feature = "base122")]
+fn embed_payload(
+	options: &mut Options,
+	encoded_dom: Vec<u8>,
+	compression: Compression,
+	payload_encoding: PayloadEncoding,
+	writer: &mut impl std::io::Write,
+) {
+	write_decoders(options, compression, payload_encoding, writer);
+
+	let (prefix, suffix) = decompression_call(options, compression);
 	writer
-		.write_all(b"local payloadBuffer=game:GetService('EncodingService'):DecompressBuffer(f(\"")
+		.write_all(prefix.as_bytes())
+		.and_then(|()| writer.write_all(b"f("))
 		.expect("failed writing piece");
 
-	// Base122 (and by extension, Base123) encoded data is valid UTF-8.
-	crate::base122::base123_encode_into(&zstd_out, writer).expect("failed writing base123 data");
+	// Base122 encoded data (with any alphabet) and dense encoded data are valid UTF-8.
+	match payload_encoding {
+		PayloadEncoding::Base122(alphabet) => {
+			let (opening, closing) = alphabet.delimiters();
+
+			writer
+				.write_all(opening.as_bytes())
+				.and_then(|()| {
+					alphabet
+						.encode_with(&mut *writer, |base_writer| {
+							compress_payload_into(&encoded_dom, compression, &mut &mut *base_writer)
+						})
+						.map(drop)
+				})
+				.and_then(|()| writer.write_all(closing.as_bytes()))
+				.expect("failed writing base122 data");
+		}
+		PayloadEncoding::Dense => {
+			write_base_decoder_arguments(
+				&compress_payload(encoded_dom, compression),
+				payload_encoding,
+				writer,
+			)
+			.expect("failed writing dense data");
+		}
+	}
 
 	writer
-		.write_all(b"\"),Enum.CompressionAlgorithm.Zstd)")
+		.write_all(b")")
+		.and_then(|()| writer.write_all(suffix.as_bytes()))
 		.expect("failed writing piece");
 }
 
+/// Writes the arguments the base decoder (`f`) takes for `data`: its encoded string literal, and for dense data its length.
+#[cfg(feature = "base122")]
+fn write_base_decoder_arguments(
+	data: &[u8],
+	payload_encoding: PayloadEncoding,
+	writer: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+	match payload_encoding {
+		PayloadEncoding::Base122(alphabet) => {
+			let (opening, closing) = alphabet.delimiters();
+
+			writer.write_all(opening.as_bytes())?;
+			alphabet.encode_with(&mut *writer, |base_writer| base_writer.write_all(data))?;
+			writer.write_all(closing.as_bytes())
+		}
+		// dense data can only be decoded with its length, which is passed after it
+		PayloadEncoding::Dense => {
+			crate::dense::write_long_bracket_string(&crate::dense::dense_encode(data), writer)?;
+			write!(writer, ",{}", data.len())
+		}
+	}
+}
+
+/// How the compressed payload is written into a script's source.
+#[cfg(feature = "base122")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadEncoding {
+	/// Base122 with an [`Alphabet`], in the kind of string literal the alphabet is made for.
+	Base122(Alphabet),
+	/// [`crate::dense`] in a long bracket string, which is about 1.25% smaller than Base123.
+	Dense,
+}
+
+#[cfg(feature = "base122")]
+impl Default for PayloadEncoding {
+	fn default() -> Self {
+		Self::Base122(Alphabet::default())
+	}
+}
+
+#[cfg(feature = "base122")]
+impl From<Alphabet> for PayloadEncoding {
+	fn from(alphabet: Alphabet) -> Self {
+		Self::Base122(alphabet)
+	}
+}
+
+/// The table in `shared` which dictionary runtimes register themselves into, keyed by their dictionary's ID.
+#[cfg(feature = "base122")]
+const DICTIONARY_RUNTIMES: &str = "AzaleaZstdDictionaries";
+
+/// Returns the key a dictionary runtime registers itself under. Raw content dictionaries have no ID, and trained ones
+/// can share theirs, so dictionaries are kept apart by a hash of their contents (like payload parts are).
+#[cfg(feature = "base122")]
+fn zstd_dictionary_key(dictionary: &[u8]) -> String {
+	use std::hash::{DefaultHasher, Hash, Hasher};
+
+	let mut hasher = DefaultHasher::new();
+	dictionary.hash(&mut hasher);
+	format!("{:016x}", hasher.finish())
+}
+
+/// Generates the dictionary runtime for [`Compression::ZstdDictionary`], which embeds `dictionary` and a pure-Luau
+/// zstd decompressor once, and registers itself into `shared` for every script compressed with `dictionary`.
+/// It also returns itself, so it can be a ModuleScript as well.
+#[cfg(feature = "base122")]
+#[must_use]
+pub fn generate_dictionary_runtime(dictionary: &[u8]) -> String {
+	use std::fmt::Write;
+
+	let mut src = String::from(include_str!("./luau/zstd.luau"));
+	src.push('\n');
+	src.push_str(&crate::base122::generate_luau_decoder(
+		&crate::base122::BASE123_ILLEGAL_BYTES,
+	));
+
+	writeln!(src, "\nlocal DICTIONARY_RUNTIMES = {DICTIONARY_RUNTIMES:?}").unwrap();
+	writeln!(
+		src,
+		"local DICTIONARY_KEY = {:?}",
+		zstd_dictionary_key(dictionary)
+	)
+	.unwrap();
+
+	let mut encoded = Vec::new();
+	crate::base122::base123_encode_into(dictionary, &mut encoded)
+		.expect("failed encoding dictionary");
+	writeln!(
+		src,
+		"local DICTIONARY = f(\"{}\")",
+		std::str::from_utf8(&encoded).expect("Base123 output is UTF-8")
+	)
+	.unwrap();
+
+	src.push_str(include_str!("./luau/dictionaryRuntime.luau"));
+
+	src
+}
+
 /// Generates an embeddable script into your writer. It is guaranteed that we will only write valid UTF-8 bytes.
+/// Returns the [`Options`] which the payload was encoded with.
 #[cfg(feature = "base122")]
-pub fn generate_embeddable_script(
-	weak_dom: &WeakDom,
+pub fn generate_embeddable_script<'dom>(
+	weak_dom: &'dom WeakDom,
 	base_requirements: Requirements,
-	level: u8,
+	compression: Compression,
+	payload_encoding: PayloadEncoding,
 
 	writer: &mut impl std::io::Write,
-) {
-	internal_create_script(weak_dom, base_requirements, level, writer);
+) -> Options<'dom> {
+	let options = internal_create_script(
+		weak_dom,
+		base_requirements,
+		compression,
+		payload_encoding,
+		writer,
+	);
 
 	writer
-		.write_all(b"\nreturn decode(payloadBuffer):GetChildren()[1]\n")
+		.write_all(
+			ScriptKind::Embeddable
+				.trailer(options.generation_requirements)
+				.as_bytes(),
+		)
 		.expect("failed writing return statement");
+
+	options
 }
 
 /// Generates a full script into your writer. It is guaranteed that we will only write valid UTF-8 bytes.
+/// Returns the [`Options`] which the payload was encoded with.
 #[cfg(feature = "base122")]
-pub fn generate_full_script(
-	weak_dom: &WeakDom,
+pub fn generate_full_script<'dom>(
+	weak_dom: &'dom WeakDom,
 	base_requirements: Requirements,
+	compression: Compression,
+	payload_encoding: PayloadEncoding,
+
+	writer: &mut impl std::io::Write,
+) -> Options<'dom> {
+	assert_module_script_root(weak_dom);
+
+	let options = internal_create_script(
+		weak_dom,
+		base_requirements,
+		compression,
+		payload_encoding,
+		writer,
+	);
+
+	writer
+		.write_all(
+			ScriptKind::Full
+				.trailer(options.generation_requirements)
+				.as_bytes(),
+		)
+		.expect("failed writing return require(...) statement");
+
+	options
+}
+
+/// Ensures that a full script will be requiring a ModuleScript.
+#[cfg(feature = "base122")]
+fn assert_module_script_root(weak_dom: &WeakDom) {
+	if let Err(error) = check_module_script_root(weak_dom) {
+		panic!("{error}");
+	}
+}
+
+/// Fails unless the root has a single child, which is a ModuleScript.
+fn check_module_script_root(weak_dom: &WeakDom) -> color_eyre::eyre::Result<()> {
+	let children = weak_dom.root().children();
+	color_eyre::eyre::ensure!(children.len() == 1, "root must have one child");
+
+	let root_first_child = weak_dom.get_by_ref(children[0]).unwrap();
+
+	color_eyre::eyre::ensure!(
+		root_first_child.class == "ModuleScript",
+		"DataModel's first child should be a module script"
+	);
+
+	Ok(())
+}
+
+/// The scripts [`generate_smallest_script`] can generate.
+#[cfg(feature = "base122")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+	/// See [`generate_full_script`].
+	Full,
+	/// See [`generate_embeddable_script`].
+	Embeddable,
+}
+
+#[cfg(feature = "base122")]
+impl ScriptKind {
+	/// The statement which ends the script, after the payload.
+	fn trailer(self, requirements: Requirements) -> String {
+		let options = if requirements.contains(Requirements::PROPERTY_ERROR_REPORT) {
+			"{returnAllRoots=true,printPropertyErrors=true}"
+		} else {
+			"{returnAllRoots=true}"
+		};
+
+		let roots = if requirements.contains(Requirements::YIELDING_DECODER) {
+			format!("decodeAsync(payloadBuffer,{options}):await()")
+		} else {
+			format!("decode(payloadBuffer,{options})")
+		};
+
+		match self {
+			Self::Full => format!("\nreturn require({roots}[1])\n"),
+			Self::Embeddable => format!("\nreturn {roots}[1]\n"),
+		}
+	}
+}
+
+/// Payload layouts which [`generate_smallest_script`] tries, on top of its base requirements.
+#[cfg(feature = "base122")]
+pub const SIZE_LAYOUTS: [Requirements; 4] = [
+	Requirements::empty(),
+	Requirements::PROPERTY_SCHEMAS,
+	Requirements::COLUMNAR_LAYOUT,
+	Requirements::COLUMNAR_LAYOUT.union(Requirements::BYTE_SHUFFLE),
+];
+
+/// Zstd levels which [`generate_smallest_script`] tries, besides the one it is given.
+#[cfg(feature = "base122")]
+const SIZE_LEVELS: [u8; 4] = [9, 15, 19, 22];
+
+/// A configuration tried by [`generate_smallest_script`], and the length of the script it generated.
+#[cfg(feature = "base122")]
+#[derive(Debug, Clone, Copy)]
+pub struct SizeTrial<'dictionary> {
+	/// One of [`SIZE_LAYOUTS`], which was added to the base requirements.
+	pub layout: Requirements,
+	pub compression: Compression<'dictionary>,
+	/// Length of the generated script in bytes, before any formatting or minifying.
+	pub script_length: usize,
+}
+
+/// Generates the smallest script (the payload after Base123 expansion, plus the decoder) it can into your writer,
+/// by trying every layout in [`SIZE_LAYOUTS`] with the zstd `level`, and then other levels, long distance matching
+/// and window sizes with the best layout. Payloads are compressed with `dictionary` if it is given.
+///
+/// It is guaranteed that we will only write valid UTF-8 bytes. Returns the [`Options`] which the payload was encoded with,
+/// and every configuration that was tried, in order. The first one is the base requirements with `level`, and the kept
+/// one is the first of the smallest ones.
+#[cfg(feature = "base122")]
+pub fn generate_smallest_script<'dom, 'dictionary>(
+	weak_dom: &'dom WeakDom,
+	base_requirements: Requirements,
+	kind: ScriptKind,
 	level: u8,
+	dictionary: Option<&'dictionary [u8]>,
+	payload_encoding: PayloadEncoding,
 
 	writer: &mut impl std::io::Write,
-) {
-	// ensure that the generated script will be requiring a ModuleScript
-	{
-		let children = weak_dom.root().children();
-		assert!(children.len() == 1, "root must have one child");
+) -> (Options<'dom>, Vec<SizeTrial<'dictionary>>) {
+	if kind == ScriptKind::Full {
+		assert_module_script_root(weak_dom);
+	}
+
+	let zstd = |parameters| match dictionary {
+		Some(dictionary) => Compression::ZstdDictionary {
+			parameters,
+			dictionary,
+		},
+		None => Compression::Zstd(parameters),
+	};
+
+	let mut encodings: Vec<(Requirements, Options<'dom>, Vec<u8>)> = Vec::new();
+	for layout in SIZE_LAYOUTS {
+		if encodings
+			.iter()
+			.any(|(tried, ..)| base_requirements | *tried == base_requirements | layout)
+		{
+			continue;
+		}
 
-		let root_first_child = weak_dom.get_by_ref(children[0]).unwrap();
+		let mut encoded_dom = Vec::new();
+		let options = crate::encoder::encode_dom_into_writer(
+			weak_dom,
+			&mut encoded_dom,
+			base_requirements | layout,
+		)
+		.expect("failed encoding dom");
 
-		assert!(
-			root_first_child.class == "ModuleScript",
-			"DataModel's first child should be a module script"
+		encodings.push((layout, options, encoded_dom));
+	}
+	let encoding_count = encodings.len();
+
+	let mut trials = Vec::new();
+	// the smallest script, and which encoding it used
+	let mut smallest: Option<(Vec<u8>, usize)> = None;
+
+	let mut try_compression = |encoding: usize, compression| {
+		let (layout, options, encoded_dom) = &mut encodings[encoding];
+		let mut script = Vec::new();
+		embed_payload(
+			options,
+			encoded_dom.clone(),
+			compression,
+			payload_encoding,
+			&mut script,
 		);
+		script.extend_from_slice(kind.trailer(options.generation_requirements).as_bytes());
+
+		trials.push(SizeTrial {
+			layout: *layout,
+			compression,
+			script_length: script.len(),
+		});
+
+		if smallest
+			.as_ref()
+			.is_none_or(|(smallest, _)| script.len() < smallest.len())
+		{
+			smallest = Some((script, encoding));
+		}
+
+		smallest.as_ref().unwrap().1
 	};
 
-	internal_create_script(weak_dom, base_requirements, level, writer);
+	let mut best_layout = 0;
+	for encoding in 0..encoding_count {
+		best_layout = try_compression(encoding, zstd(ZstdParameters::level(level)));
+	}
+
+	for other_level in SIZE_LEVELS {
+		for long_distance_matching in [false, true] {
+			for window_log in [None, Some(27)] {
+				let parameters = ZstdParameters {
+					level: other_level,
+					long_distance_matching,
+					window_log,
+				};
 
+				// already tried with every layout
+				if parameters != ZstdParameters::level(level) {
+					try_compression(best_layout, zstd(parameters));
+				}
+			}
+		}
+	}
+
+	let (script, encoding) = smallest.unwrap();
 	writer
-		.write_all(b"\nreturn require(decode(payloadBuffer):GetChildren()[1])\n")
-		.expect("failed writing return require(...) statement");
+		.write_all(&script)
+		.expect("failed writing smallest script");
+
+	(encodings.swap_remove(encoding).1, trials)
+}
+
+/// The table in `shared` which payload parts register themselves into, keyed by their payload's ID.
+#[cfg(feature = "base122")]
+const PAYLOAD_PARTS: &str = "AzaleaPayloadParts";
+
+/// Most bytes a part script may need around its encoded data: long bracket levels and the dense data length.
+#[cfg(feature = "base122")]
+const PART_SLACK: usize = 32;
+
+/// A script generated by [`generate_multipart_script`], which is split into a loader and the parts it gathers.
+#[cfg(feature = "base122")]
+#[derive(Debug, Clone)]
+pub struct MultipartScript {
+	/// Decodes the payload once every part has run, and ends like the [`ScriptKind`] it was generated as.
+	pub loader: String,
+	/// Every part of the compressed payload, in order. Parts must run before the loader (in any order),
+	/// and return what they register, so they can be ModuleScripts as well.
+	pub parts: Vec<String>,
+}
+
+/// Generates a script whose compressed payload is split across part scripts of at most `part_size` bytes, for hosts
+/// which limit how large a script can be. Parts register themselves into `shared`, and the loader (which holds the
+/// decoder) checks that every part arrived intact before decoding. It is guaranteed that we will only generate valid UTF-8.
+///
+/// Returns the [`Options`] which the payload was encoded with. Fails if `part_size` can't fit any data, or if a
+/// [`ScriptKind::Full`] script's root isn't a single ModuleScript.
+#[cfg(feature = "base122")]
+pub fn generate_multipart_script<'dom>(
+	weak_dom: &'dom WeakDom,
+	base_requirements: Requirements,
+	kind: ScriptKind,
+	compression: Compression,
+	payload_encoding: PayloadEncoding,
+	part_size: usize,
+) -> color_eyre::eyre::Result<(Options<'dom>, MultipartScript)> {
+	use std::fmt::Write;
+	use std::hash::{DefaultHasher, Hash, Hasher};
+
+	if kind == ScriptKind::Full {
+		check_module_script_root(weak_dom)?;
+	}
+
+	let mut encoded_dom = Vec::new();
+	let mut options =
+		crate::encoder::encode_dom_into_writer(weak_dom, &mut encoded_dom, base_requirements)?;
+	let compressed = compress_payload(encoded_dom, compression);
+
+	// different payloads can be loaded at the same time, so parts are kept apart by a hash of their payload.
+	// the encoded data is the last string literal in parts, which extraction relies on (see crate::extract)
+	let mut hasher = DefaultHasher::new();
+	compressed.hash(&mut hasher);
+	let payload_id = format!("{:016x}", hasher.finish());
+
+	let part_glue = |index: usize| {
+		(
+			format!(
+				"local id='{payload_id}' local parts=shared.{PAYLOAD_PARTS} or {{}}shared.{PAYLOAD_PARTS}=parts parts[id]=parts[id] or {{}}\nparts[id][{index}]=function(f)return f("
+			),
+			format!(")end return parts[id][{index}]\n"),
+		)
+	};
+
+	let glue_length = {
+		let (prefix, suffix) = part_glue(usize::MAX);
+		prefix.len() + suffix.len()
+	};
+	// Base122 and dense data take at most 8 bytes of source for every 7 bytes of data
+	let data_per_part = part_size.saturating_sub(glue_length + PART_SLACK) * 7 / 8;
+	color_eyre::eyre::ensure!(
+		data_per_part > 0,
+		"parts must be larger than {} bytes",
+		glue_length + PART_SLACK
+	);
+
+	let mut parts = Vec::new();
+	let mut part_lengths = Vec::new();
+	for (index, chunk) in compressed.chunks(data_per_part).enumerate() {
+		let (prefix, suffix) = part_glue(index + 1);
+
+		let mut part = prefix.into_bytes();
+		write_base_decoder_arguments(chunk, payload_encoding, &mut part)?;
+		part.extend_from_slice(suffix.as_bytes());
+		color_eyre::eyre::ensure!(
+			part.len() <= part_size,
+			"part {} is {} bytes, which is larger than {part_size} bytes",
+			index + 1,
+			part.len()
+		);
+
+		parts.push(String::from_utf8(part).expect("Base122 and dense output is UTF-8"));
+		part_lengths.push(chunk.len().to_string());
+	}
+
+	let mut loader = Vec::new();
+	write_decoders(&mut options, compression, payload_encoding, &mut loader);
+
+	let mut loader = String::from_utf8(loader).expect("decoders are valid UTF-8");
+	writeln!(loader, "\nlocal PAYLOAD_PARTS = {PAYLOAD_PARTS:?}").unwrap();
+	writeln!(loader, "local PAYLOAD_ID = {payload_id:?}").unwrap();
+	writeln!(
+		loader,
+		"local PART_LENGTHS = {{ {} }}",
+		part_lengths.join(", ")
+	)
+	.unwrap();
+	loader.push_str(include_str!("./luau/payloadParts.luau"));
+
+	let (prefix, suffix) = decompression_call(&options, compression);
+	write!(loader, "{prefix}gatherPayloadParts(){suffix}").unwrap();
+	loader.push_str(&kind.trailer(options.generation_requirements));
+
+	Ok((options, MultipartScript { loader, parts }))
+}
+
+/// Generates a patch script into your writer, which returns a function that updates a tree decoded from `old` into `new`.
+/// It is guaranteed that we will only write valid UTF-8 bytes. Returns the [`Options`] which the patch was encoded with.
+///
+/// See [`crate::diff::diff_doms_into_writer`] for which models can be diffed.
+#[cfg(feature = "base122")]
+pub fn generate_patch_script<'dom>(
+	old: &WeakDom,
+	new: &'dom WeakDom,
+	base_requirements: Requirements,
+	compression: Compression,
+	payload_encoding: PayloadEncoding,
+
+	writer: &mut impl std::io::Write,
+) -> color_eyre::eyre::Result<Options<'dom>> {
+	let mut patch = Vec::new();
+	let mut options = crate::diff::diff_doms_into_writer(old, new, &mut patch, base_requirements)?;
+
+	embed_payload(&mut options, patch, compression, payload_encoding, writer);
+
+	writer
+		.write_all(
+			if options
+				.generation_requirements
+				.contains(Requirements::PROPERTY_ERROR_REPORT)
+			{
+				b"\nreturn function(root: Instance): Instance\n\tlocal patchedRoot, propertyErrors = applyPatch(root, payloadBuffer)\n\twarnPropertyErrors(propertyErrors)\n\treturn patchedRoot\nend\n"
+			} else {
+				b"\nreturn function(root: Instance): Instance\n\treturn applyPatch(root, payloadBuffer)\nend\n"
+			},
+		)
+		.expect("failed writing return statement");
+
+	Ok(options)
+}
+
+/// Generates the client script for [`crate::serve`], which decodes every served model and keeps it in sync with `server_url`.
+/// `model_names` are only used for messages, and must be in the order the server serves them in.
+#[cfg(feature = "base122")]
+#[must_use]
+pub fn generate_live_sync_client(server_url: &str, model_names: &[String]) -> String {
+	use std::fmt::Write;
+
+	let mut src = generate_with_options(&full_decoder_options(Requirements::all().difference(
+		Requirements::USE_NOVEL_INLINING
+			| Requirements::RETURN_DECODE
+			| Requirements::LZ_DECOMPRESSOR
+			| Requirements::YIELDING_DECODER
+			| Requirements::PROPERTY_ERROR_REPORT,
+	)));
+
+	src.push_str(&crate::base122::generate_luau_decoder(
+		&crate::base122::BASE123_ILLEGAL_BYTES,
+	));
+
+	// Rust's string escapes are valid in Luau as well
+	writeln!(src, "\nlocal SERVER_URL = {server_url:?}").unwrap();
+	writeln!(
+		src,
+		"local MODEL_NAMES = {{ {} }}",
+		model_names
+			.iter()
+			.map(|name| format!("{name:?}"))
+			.collect::<Vec<_>>()
+			.join(", ")
+	)
+	.unwrap();
+
+	src.push_str(include_str!("./luau/liveSyncClient.luau"));
+
+	src
 }
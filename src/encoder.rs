@@ -2,15 +2,17 @@
 
 use crate::{
 	emit::{Options, Requirements},
-	spec::TypeId,
+	reflection::{DefaultProperties, PropertyAccess, ScriptableProperties},
+	spec::{ColumnKind, FORMAT_MAGIC, FORMAT_VERSION, FormatFlags, RecordKind, TypeId},
 };
 use color_eyre::eyre::{self, WrapErr};
 use rbx_dom_weak::{
-	Instance, WeakDom,
+	Instance, Ustr, UstrSet, WeakDom,
 	types::{BinaryString, ContentType, Ref, SharedString, Variant},
 };
 use std::{
 	collections::{HashMap, HashSet},
+	hash::{DefaultHasher, Hash, Hasher},
 	io::Write,
 };
 
@@ -36,10 +38,155 @@ fn write_nullstring(target: &mut impl Write, string: &[u8]) -> eyre::Result<()>
 	Ok(())
 }
 
+/// Strings which repeat across a model (class names, property names, attribute keys, tags and BrickColor names).
+///
+/// They are written once as a table at the start of the payload, and referenced by their LEB128 encoded index.
+#[derive(Default)]
+pub(crate) struct StringTable {
+	indices: HashMap<String, usize>,
+	strings: Vec<String>,
+}
+
+impl StringTable {
+	/// Returns the index of `string`, adding it to the table if it is not already present.
+	fn intern(&mut self, string: &str) -> usize {
+		if let Some(&index) = self.indices.get(string) {
+			return index;
+		}
+
+		let index = self.strings.len();
+		self.strings.push(string.to_owned());
+		self.indices.insert(string.to_owned(), index);
+
+		index
+	}
+
+	pub(crate) fn write_into(&self, target: &mut impl Write) -> eyre::Result<()> {
+		leb128::write::unsigned(target, self.strings.len().try_into()?)
+			.wrap_err("failed writing string table length as leb128 encoded unsigned integer")?;
+
+		for string in &self.strings {
+			write_varstring(target, string.as_bytes()).wrap_err("failed writing interned string")?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Strings and BinaryStrings shorter than this are always written inline, as referencing them would barely save anything.
+const MIN_BLOB_LENGTH: usize = 32;
+
+/// Returns the contents of `variant` if it can be stored in a [`BlobTable`], alongside the type id which references it.
+fn blob_contents(variant: &Variant) -> Option<(TypeId, &[u8])> {
+	let (type_id, contents) = match variant {
+		Variant::String(string) => (TypeId::StringBlob, string.as_bytes()),
+		Variant::BinaryString(binary_string) => (TypeId::BinaryStringBlob, binary_string.as_ref()),
+		Variant::SharedString(shared_string) => (TypeId::BinaryStringBlob, shared_string.data()),
+		_ => return None,
+	};
+
+	(contents.len() >= MIN_BLOB_LENGTH).then_some((type_id, contents))
+}
+
+/// Large strings and BinaryStrings which are encoded more than once (such as the Source of vendored packages, or SharedStrings),
+/// see [`Requirements::BLOB_TABLE`].
+///
+/// They are written once as a table after the string table, keyed by their contents. Properties reference them by their LEB128 encoded index,
+/// behind a [`TypeId::StringBlob`] or [`TypeId::BinaryStringBlob`] so decoders know which variant to rebuild.
+#[derive(Default)]
+pub(crate) struct BlobTable<'dom> {
+	indices: HashMap<&'dom [u8], usize>,
+	blobs: Vec<&'dom [u8]>,
+}
+
+impl<'dom> BlobTable<'dom> {
+	/// Collects the blobs which are encoded more than once in `weak_dom` (except in the subtrees in `clones`), in encoding order.
+	fn from_dom(
+		weak_dom: &'dom WeakDom,
+		property_overrides: &PropertyOverrides,
+		clones: &HashMap<Ref, Ref>,
+		requirements: Requirements,
+	) -> Self {
+		let inlines_module_sources = requirements.contains(Requirements::USE_NOVEL_INLINING);
+		let mut counts: HashMap<&[u8], usize> = HashMap::new();
+		let mut first_occurrences = Vec::new();
+
+		let mut stack = vec![weak_dom.root_ref()];
+		while let Some(instance_referent) = stack.pop() {
+			if clones.contains_key(&instance_referent) {
+				continue;
+			}
+
+			let instance = weak_dom.get_by_ref(instance_referent).unwrap();
+
+			for (property, value) in property_overrides.encoded_properties(instance) {
+				// novel inlining moves ModuleScript sources into the generated script, see encode_property
+				if inlines_module_sources && instance.class == "ModuleScript" && property == "Source" {
+					continue;
+				}
+
+				if let Some((_, contents)) = blob_contents(value) {
+					let count = counts.entry(contents).or_default();
+					if *count == 0 {
+						first_occurrences.push(contents);
+					}
+
+					*count += 1;
+				}
+			}
+
+			stack.extend(instance.children().iter().rev().copied());
+		}
+
+		let mut blob_table = Self::default();
+		for contents in first_occurrences {
+			if counts[contents] > 1 {
+				blob_table.indices.insert(contents, blob_table.blobs.len());
+				blob_table.blobs.push(contents);
+			}
+		}
+
+		blob_table
+	}
+
+	/// Returns the type id and index which reference `variant`, if its contents are in the table.
+	fn reference(&self, variant: &Variant) -> Option<(TypeId, usize)> {
+		if self.blobs.is_empty() {
+			return None;
+		}
+
+		let (type_id, contents) = blob_contents(variant)?;
+		Some((type_id, *self.indices.get(contents)?))
+	}
+
+	fn write_into(&self, target: &mut impl Write) -> eyre::Result<()> {
+		leb128::write::unsigned(target, self.blobs.len().try_into()?)
+			.wrap_err("failed writing blob table length as leb128 encoded unsigned integer")?;
+
+		for blob in &self.blobs {
+			write_varstring(target, blob).wrap_err("failed writing blob")?;
+		}
+
+		Ok(())
+	}
+}
+
+/// NOTE: This function does not add any sort of type id.
+pub(crate) fn write_interned_string(
+	target: &mut impl Write,
+	string: &str,
+	string_table: &mut StringTable,
+) -> eyre::Result<()> {
+	leb128::write::unsigned(target, string_table.intern(string).try_into()?)
+		.wrap_err("failed writing interned string index as leb128 encoded unsigned integer")?;
+
+	Ok(())
+}
+
 /// This function writes the type id [`TypeId::String`] and then a varstring.
 ///
 /// It is provided so you can write a [`Variant::String`] without having to clone data.
-fn write_string_variant(target: &mut impl Write, string: &str) -> eyre::Result<()> {
+pub(crate) fn write_string_variant(target: &mut impl Write, string: &str) -> eyre::Result<()> {
 	target
 		.write_all(&[TypeId::String as u8])
 		.wrap_err("failed to write type id for String")?;
@@ -49,10 +196,11 @@ fn write_string_variant(target: &mut impl Write, string: &str) -> eyre::Result<(
 	Ok(())
 }
 
-fn write_variant(
+pub(crate) fn write_variant(
 	target: &mut impl Write,
 	variant: &Variant,
 	referent_map: &mut HashMap<Ref, usize>,
+	string_table: &mut StringTable,
 ) -> eyre::Result<()> {
 	match variant {
 		// Attribute + String name -> Variant
@@ -65,11 +213,11 @@ fn write_variant(
 				.wrap_err("failed writing attributes length as leb128 encoded unsigned integer")?;
 
 			for (attribute_name, attribute_variant) in attributes {
-				write_nullstring(target, attribute_name.as_bytes())
-					.wrap_err("failed writing attribute name as nullstring")?;
-				write_variant(target, attribute_variant, referent_map).wrap_err_with(|| {
-					format!("failed writing attribute variant for attribute name {attribute_name}")
-				})?;
+				write_interned_string(target, attribute_name, string_table)
+					.wrap_err("failed writing attribute name as interned string")?;
+				write_variant(target, attribute_variant, referent_map, string_table).wrap_err_with(
+					|| format!("failed writing attribute variant for attribute name {attribute_name}"),
+				)?;
 			}
 		}
 		Variant::Axes(axes) => {
@@ -94,8 +242,8 @@ fn write_variant(
 			target
 				.write_all(&[TypeId::BrickColor as u8])
 				.wrap_err("failed writing type id for BrickColor")?;
-			write_nullstring(target, name.as_bytes())
-				.wrap_err("failed writing name for BrickColor as nullstring")?;
+			write_interned_string(target, &name, string_table)
+				.wrap_err("failed writing name for BrickColor as interned string")?;
 		}
 		// https://dom.rojo.space/binary.html
 		Variant::CFrame(cframe) => {
@@ -203,7 +351,7 @@ fn write_variant(
 					target
 						.write_all(&[TypeId::ContentObject as u8])
 						.wrap_err("failed to write type id for nil Content")?;
-					write_variant(target, &Variant::Ref(*referent), referent_map)?;
+					write_variant(target, &Variant::Ref(*referent), referent_map, string_table)?;
 				}
 				ContentType::Uri(string) => {
 					target
@@ -286,8 +434,13 @@ fn write_variant(
 				* warning: casting `i64` to `f64` causes a loss of precision (`i64` is 64 bits wide, but `f64`'s mantissa is only 52 bits wide)
 				* ^ this is fine, luau numbers are f64's anyway
 			 */
-			Ok(int) => write_variant(target, &Variant::Int32(int), referent_map)?,
-			Err(_) => write_variant(target, &Variant::Float64((*int) as f64), referent_map)?,
+			Ok(int) => write_variant(target, &Variant::Int32(int), referent_map, string_table)?,
+			Err(_) => write_variant(
+				target,
+				&Variant::Float64((*int) as f64),
+				referent_map,
+				string_table,
+			)?,
 		},
 		Variant::MaterialColors(colors) => {
 			let bytes = colors.encode();
@@ -331,7 +484,12 @@ fn write_variant(
 					.write_all(&[TypeId::None as u8])
 					.wrap_err("failed writing type id for OptionalCFrame")?;
 			}
-			Some(cframe) => write_variant(target, &Variant::CFrame(*cframe), referent_map)?,
+			Some(cframe) => write_variant(
+				target,
+				&Variant::CFrame(*cframe),
+				referent_map,
+				string_table,
+			)?,
 		},
 		Variant::PhysicalProperties(properties) => match properties {
 			rbx_dom_weak::types::PhysicalProperties::Default => {
@@ -468,6 +626,7 @@ fn write_variant(
 			target,
 			&Variant::BinaryString(BinaryString::from(string.data())),
 			referent_map,
+			string_table,
 		)?,
 
 		Variant::String(string) => write_string_variant(target, string.as_str())?,
@@ -480,7 +639,8 @@ fn write_variant(
 				.wrap_err("failed writing tags length as leb128 encoded unsigned integer")?;
 
 			for tag in tags.iter() {
-				write_nullstring(target, tag.as_bytes()).wrap_err("failed writing tag as nullstring")?;
+				write_interned_string(target, tag, string_table)
+					.wrap_err("failed writing tag as interned string")?;
 			}
 		}
 		Variant::UDim(udim) => {
@@ -555,6 +715,7 @@ fn write_variant(
 			target,
 			&Variant::SharedString(SharedString::new(net_asset_ref.data().to_vec())),
 			referent_map,
+			string_table,
 		)?,
 
 		_ => eyre::bail!("unimplemented VariantType: {:#?}", variant.ty()),
@@ -563,29 +724,502 @@ fn write_variant(
 	Ok(())
 }
 
+/// A property which scripts can't write, see [`Requirements::SKIP_UNWRITABLE_PROPERTIES`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnwritableProperty {
+	pub class: Ustr,
+	pub property: Ustr,
+	/// The writable property which it was encoded as instead, if it is an alias of one (and the instance lacks that property).
+	pub encoded_as: Option<Ustr>,
+	/// How many instances of the class have the property.
+	pub instance_count: usize,
+}
+
+/// Which properties of each instance are encoded, and under which names.
+#[derive(Default)]
+pub(crate) struct PropertyOverrides {
+	/// Properties which are left out of the payload.
+	dropped: HashSet<(Ref, Ustr)>,
+	/// Properties which are encoded under another name, mapped to that name.
+	renamed: HashMap<(Ref, Ustr), Ustr>,
+	/// The inverse of `renamed`.
+	sources: HashMap<(Ref, Ustr), Ustr>,
+}
+
+impl PropertyOverrides {
+	/// Drops properties which equal their default in `default_properties`, and renames or drops properties which scripts can't write
+	/// according to `scriptable_properties` (which are reported).
+	fn collect(
+		weak_dom: &WeakDom,
+		default_properties: Option<&DefaultProperties>,
+		scriptable_properties: Option<&ScriptableProperties>,
+	) -> (Self, Vec<UnwritableProperty>) {
+		let mut overrides = Self::default();
+		let mut unwritable_properties: HashMap<(Ustr, Ustr), UnwritableProperty> = HashMap::new();
+
+		for instance in weak_dom.descendants() {
+			let referent = instance.referent();
+
+			for (property, value) in &instance.properties {
+				let access = scriptable_properties
+					.map_or(PropertyAccess::Writable, |scriptable_properties| {
+						scriptable_properties.access(&instance.class, property, value)
+					});
+
+				// aliases are dropped if the instance already has the property they alias
+				let name = match access {
+					PropertyAccess::Writable => Some(*property),
+					PropertyAccess::AliasOf(canonical) => Some(Ustr::from(canonical))
+						.filter(|canonical| !instance.properties.contains_key(canonical)),
+					PropertyAccess::Unwritable => None,
+				};
+
+				if access != PropertyAccess::Writable {
+					unwritable_properties
+						.entry((instance.class, *property))
+						.or_insert_with(|| UnwritableProperty {
+							class: instance.class,
+							property: *property,
+							encoded_as: name,
+							instance_count: 0,
+						})
+						.instance_count += 1;
+				}
+
+				let Some(name) = name.filter(|name| {
+					!default_properties.is_some_and(|default_properties| {
+						default_properties.is_default(&instance.class, name, value)
+					})
+				}) else {
+					overrides.dropped.insert((referent, *property));
+					continue;
+				};
+
+				if name != *property {
+					overrides.renamed.insert((referent, *property), name);
+					overrides.sources.insert((referent, name), *property);
+				}
+			}
+		}
+
+		let mut unwritable_properties = unwritable_properties.into_values().collect::<Vec<_>>();
+		unwritable_properties.sort_unstable_by(|a, b| {
+			(a.class.as_str(), a.property.as_str()).cmp(&(b.class.as_str(), b.property.as_str()))
+		});
+
+		(overrides, unwritable_properties)
+	}
+
+	/// Returns the properties of `instance` which are encoded, under the names they are encoded as.
+	fn encoded_properties<'dom>(&self, instance: &'dom Instance) -> Vec<(Ustr, &'dom Variant)> {
+		let referent = instance.referent();
+
+		instance
+			.properties
+			.iter()
+			.filter(|(property, _)| !self.dropped.contains(&(referent, **property)))
+			.map(|(property, value)| {
+				let name = self.renamed.get(&(referent, *property)).unwrap_or(property);
+				(*name, value)
+			})
+			.collect()
+	}
+
+	/// Returns the value which is encoded as `property` on `instance`, see [`Self::encoded_properties`].
+	fn encoded_property<'dom>(
+		&self,
+		instance: &'dom Instance,
+		property: Ustr,
+	) -> Option<&'dom Variant> {
+		let referent = instance.referent();
+		let source = self
+			.sources
+			.get(&(referent, property))
+			.copied()
+			.unwrap_or(property);
+
+		instance
+			.properties
+			.get(&source)
+			.filter(|_| !self.dropped.contains(&(referent, source)))
+			.filter(|_| source != property || !self.renamed.contains_key(&(referent, property)))
+	}
+}
+
+/// Property names shared by every instance of a class, see [`Requirements::PROPERTY_SCHEMAS`].
+///
+/// A schema contains the union of the properties of every instance of its class, sorted by name.
+/// Instances which lack one of those properties encode it as [`TypeId::None`].
+#[derive(Default)]
+pub(crate) struct SchemaTable {
+	indices: HashMap<Ustr, usize>,
+	schemas: Vec<(Ustr, Vec<Ustr>)>,
+}
+
+impl SchemaTable {
+	/// Collects a schema for every class in `weak_dom` (except for the subtrees in `clones`), in encoding order.
+	fn from_dom(
+		weak_dom: &WeakDom,
+		property_overrides: &PropertyOverrides,
+		clones: &HashMap<Ref, Ref>,
+	) -> Self {
+		let mut indices = HashMap::new();
+		let mut schemas: Vec<(Ustr, UstrSet)> = Vec::new();
+
+		let mut stack = vec![weak_dom.root_ref()];
+		while let Some(instance_referent) = stack.pop() {
+			if clones.contains_key(&instance_referent) {
+				continue;
+			}
+
+			let instance = weak_dom.get_by_ref(instance_referent).unwrap();
+
+			let index = *indices.entry(instance.class).or_insert_with(|| {
+				schemas.push((instance.class, UstrSet::default()));
+				schemas.len() - 1
+			});
+			schemas[index].1.extend(
+				property_overrides
+					.encoded_properties(instance)
+					.into_iter()
+					.map(|(property, _)| property),
+			);
+
+			stack.extend(instance.children().iter().rev().copied());
+		}
+
+		let schemas = schemas
+			.into_iter()
+			.map(|(class, property_names)| {
+				let mut property_names = property_names.into_iter().collect::<Vec<_>>();
+				property_names.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+
+				(class, property_names)
+			})
+			.collect();
+
+		Self { indices, schemas }
+	}
+
+	fn write_into(
+		&self,
+		target: &mut impl Write,
+		string_table: &mut StringTable,
+	) -> eyre::Result<()> {
+		leb128::write::unsigned(target, self.schemas.len().try_into()?)
+			.wrap_err("failed writing schema table length as leb128 encoded unsigned integer")?;
+
+		for (class, property_names) in &self.schemas {
+			write_interned_string(target, class, string_table)
+				.wrap_err("failed writing interned string for schema ClassName")?;
+			leb128::write::unsigned(target, property_names.len().try_into()?)
+				.wrap_err("failed writing schema property count as leb128 encoded unsigned integer")?;
+
+			for property in property_names {
+				write_interned_string(target, property, string_table)
+					.wrap_err("failed writing schema property name as interned string")?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Returns the subtree of `referent` in encoding order (depth first, children in order), which is also the order of `GetDescendants`.
+fn subtree_in_encoding_order(weak_dom: &WeakDom, referent: Ref) -> Vec<&Instance> {
+	let mut instances = Vec::new();
+
+	let mut stack = vec![referent];
+	while let Some(instance_referent) = stack.pop() {
+		let instance = weak_dom.get_by_ref(instance_referent).unwrap();
+		instances.push(instance);
+		stack.extend(instance.children().iter().rev().copied());
+	}
+
+	instances
+}
+
+/// Hashes the parts of `variant` which are cheap to hash, anything else is compared by [`subtrees_equal`].
+fn hash_variant(variant: &Variant, hasher: &mut impl Hasher) {
+	std::mem::discriminant(variant).hash(hasher);
+
+	match variant {
+		Variant::Bool(value) => value.hash(hasher),
+		Variant::String(value) => value.hash(hasher),
+		Variant::Int32(value) => value.hash(hasher),
+		Variant::Int64(value) => value.hash(hasher),
+		Variant::Float32(value) => value.to_bits().hash(hasher),
+		Variant::Float64(value) => value.to_bits().hash(hasher),
+		Variant::Enum(value) => value.to_u32().hash(hasher),
+		Variant::Color3(color) => [color.r, color.g, color.b].map(f32::to_bits).hash(hasher),
+		Variant::Color3uint8(color) => [color.r, color.g, color.b].hash(hasher),
+		Variant::Vector2(vector) => [vector.x, vector.y].map(f32::to_bits).hash(hasher),
+		Variant::Vector3(vector) => [vector.x, vector.y, vector.z]
+			.map(f32::to_bits)
+			.hash(hasher),
+		Variant::CFrame(cframe) => [cframe.position.x, cframe.position.y, cframe.position.z]
+			.map(f32::to_bits)
+			.hash(hasher),
+		Variant::UDim2(udim2) => (
+			[udim2.x.scale, udim2.y.scale].map(f32::to_bits),
+			[udim2.x.offset, udim2.y.offset],
+		)
+			.hash(hasher),
+
+		_ => {}
+	}
+}
+
+/// Returns the referent which `variant` points to, if it points to an instance.
+pub(crate) fn variant_referent(variant: &Variant) -> Option<Ref> {
+	match variant {
+		Variant::Ref(referent) if referent.is_some() => Some(*referent),
+		Variant::Content(content) => match content.value() {
+			ContentType::Object(referent) if referent.is_some() => Some(*referent),
+			_ => None,
+		},
+
+		_ => None,
+	}
+}
+
+/// Returns whether `:Clone()`ing the subtree of `a` results in the subtree of `b`, ignoring the names of `a` and `b` themselves.
+///
+/// Referent properties must point to the same place inside of both subtrees, or to the same instance outside of them.
+fn subtrees_equal(
+	weak_dom: &WeakDom,
+	property_overrides: &PropertyOverrides,
+	a: Ref,
+	b: Ref,
+) -> bool {
+	let (a, b) = (
+		subtree_in_encoding_order(weak_dom, a),
+		subtree_in_encoding_order(weak_dom, b),
+	);
+
+	if a.len() != b.len() {
+		return false;
+	}
+
+	let indices = |subtree: &[&Instance]| -> HashMap<Ref, usize> {
+		subtree
+			.iter()
+			.enumerate()
+			.map(|(index, instance)| (instance.referent(), index))
+			.collect()
+	};
+	let (a_indices, b_indices) = (indices(&a), indices(&b));
+
+	let values_equal = |a_value: &Variant, b_value: &Variant| match (
+		variant_referent(a_value),
+		variant_referent(b_value),
+	) {
+		(Some(a_referent), Some(b_referent)) => {
+			a_value.ty() == b_value.ty()
+				&& match (a_indices.get(&a_referent), b_indices.get(&b_referent)) {
+					(Some(a_index), Some(b_index)) => a_index == b_index,
+					(None, None) => a_referent == b_referent,
+					_ => false,
+				}
+		}
+		_ => a_value == b_value,
+	};
+
+	// depth first order and equal child counts mean both subtrees have the same shape
+	a.iter().zip(&b).enumerate().all(|(index, (a, b))| {
+		let a_properties = property_overrides.encoded_properties(a);
+
+		a.class == b.class
+			&& (index == 0 || a.name == b.name)
+			&& a.children().len() == b.children().len()
+			&& a_properties.len() == property_overrides.encoded_properties(b).len()
+			&& a_properties.iter().all(|(property, a_value)| {
+				property_overrides
+					.encoded_property(b, *property)
+					.is_some_and(|b_value| values_equal(a_value, b_value))
+			})
+	})
+}
+
+/// Finds every subtree which repeats an earlier subtree (in encoding order), mapped to the root of that earlier subtree.
+/// See [`Requirements::DEDUPLICATE_SUBTREES`].
+fn find_subtree_clones(
+	weak_dom: &WeakDom,
+	property_overrides: &PropertyOverrides,
+) -> HashMap<Ref, Ref> {
+	let archivable = Ustr::from("Archivable");
+
+	// fingerprints are computed children first, subtrees which can't be cloned have none
+	let mut fingerprints: HashMap<Ref, Option<u64>> = HashMap::new();
+	for instance in subtree_in_encoding_order(weak_dom, weak_dom.root_ref())
+		.into_iter()
+		.rev()
+	{
+		// scripts are created through NewScript (and friends), and Clone() skips instances which aren't Archivable
+		let cloneable = !matches!(
+			instance.class.as_str(),
+			"Script" | "LocalScript" | "ModuleScript"
+		) && instance.properties.get(&archivable) != Some(&Variant::Bool(false));
+
+		let fingerprint = cloneable
+			.then(|| {
+				let mut hasher = DefaultHasher::new();
+				instance.class.hash(&mut hasher);
+
+				let mut properties = property_overrides.encoded_properties(instance);
+				properties.sort_unstable_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+				for (property, value) in properties {
+					property.hash(&mut hasher);
+					hash_variant(value, &mut hasher);
+				}
+
+				for child in instance.children() {
+					weak_dom.get_by_ref(*child).unwrap().name.hash(&mut hasher);
+					fingerprints[child]?.hash(&mut hasher);
+				}
+
+				Some(hasher.finish())
+			})
+			.flatten();
+
+		fingerprints.insert(instance.referent(), fingerprint);
+	}
+
+	let mut candidates: HashMap<u64, Vec<Ref>> = HashMap::new();
+	let mut clones = HashMap::new();
+
+	// the root is never cloned, and the descendants of clones are never encoded
+	let mut stack = weak_dom
+		.root()
+		.children()
+		.iter()
+		.rev()
+		.copied()
+		.collect::<Vec<_>>();
+	while let Some(instance_referent) = stack.pop() {
+		let instance = weak_dom.get_by_ref(instance_referent).unwrap();
+
+		// cloning a single instance without any properties saves nothing
+		let worthwhile = !instance.children().is_empty()
+			|| !property_overrides.encoded_properties(instance).is_empty();
+
+		if let Some(fingerprint) = fingerprints[&instance_referent].filter(|_| worthwhile) {
+			let candidates = candidates.entry(fingerprint).or_default();
+
+			if let Some(&source) = candidates
+				.iter()
+				.find(|&&source| subtrees_equal(weak_dom, property_overrides, source, instance_referent))
+			{
+				clones.insert(instance_referent, source);
+				continue;
+			}
+
+			candidates.push(instance_referent);
+		}
+
+		stack.extend(instance.children().iter().rev().copied());
+	}
+
+	clones
+}
+
+/// Encodes a single property value of an [`Instance`], the property name is written by the caller.
+pub(crate) fn encode_property<'dom>(
+	instance: &'dom Instance,
+	property: &str,
+	value: &'dom Variant,
+	options: &mut Options<'dom>,
+	buffer: &mut impl Write,
+) -> eyre::Result<()> {
+	if instance.class == "ModuleScript"
+		&& property == "Source"
+		&& options
+			.generation_requirements
+			.contains(Requirements::USE_NOVEL_INLINING)
+		&& let Variant::String(source) = value
+	{
+		// this won't panic because we write the instance ref before calling encode_property and calling write_variant
+		// on a Ref means the referent_map is always populated with a usize for the Ref
+		options.module_script_sources.insert(
+			*options.referent_map.get(&instance.referent()).unwrap(),
+			source,
+		);
+
+		buffer
+			.write_all(&[TypeId::None as u8])
+			.wrap_err("failed writing None type id for null Source (for novel inlining)")?;
+
+		return Ok(());
+	}
+
+	if let Some((type_id, index)) = options.blob_table.reference(value) {
+		options.known_needed_type_ids.insert(type_id);
+
+		buffer
+			.write_all(&[type_id as u8])
+			.wrap_err("failed writing type id for blob reference")?;
+		leb128::write::unsigned(buffer, index.try_into()?)
+			.wrap_err("failed writing blob index as leb128 encoded unsigned integer")?;
+
+		return Ok(());
+	}
+
+	options
+		.known_needed_type_ids
+		.extend(crate::spec::variant_to_type_id(value));
+
+	write_variant(
+		buffer,
+		value,
+		&mut options.referent_map,
+		&mut options.string_table,
+	)
+	.wrap_err("failed writing property variant")
+}
+
 /// Encodes an [`Instance`] alongside it's [`WeakDom`] with a referent map into a writer which implements [`Write`].
 ///
 /// NOTE: This function does not encode the instance's children at all.
-fn encode_instance<'dom>(
+pub(crate) fn encode_instance<'dom>(
 	instance: &'dom Instance,
 	options: &mut Options<'dom>,
 	buffer: &mut impl Write,
 ) -> eyre::Result<()> {
 	let referent_map = &mut options.referent_map;
+	let string_table = &mut options.string_table;
+
+	if options.format_flags.contains(FormatFlags::CLONED_SUBTREES) {
+		buffer
+			.write_all(&[RecordKind::Instance as u8])
+			.wrap_err("failed writing record kind")?;
+	}
 
 	write_varstring(buffer, instance.name.as_bytes())?;
 
-	write_nullstring(buffer, instance.class.as_bytes())
-		.wrap_err("failed writing nullstring for instance ClassName")?;
+	let schema_index = if let Some(schemas) = &options.schemas {
+		let schema_index = schemas.indices[&instance.class];
+		leb128::write::unsigned(buffer, schema_index.try_into()?)
+			.wrap_err("failed writing schema index as leb128 encoded unsigned integer")?;
 
-	write_variant(buffer, &Variant::Ref(instance.referent()), referent_map)?;
-	write_variant(buffer, &Variant::Ref(instance.parent()), referent_map)?;
+		Some(schema_index)
+	} else {
+		write_interned_string(buffer, &instance.class, string_table)
+			.wrap_err("failed writing interned string for instance ClassName")?;
 
-	// Properties
-	buffer.write_all(
-		&(u16::try_from(instance.properties.len())
-			.wrap_err("failed truncating properties length to u16")?)
-		.to_le_bytes(),
+		None
+	};
+
+	write_variant(
+		buffer,
+		&Variant::Ref(instance.referent()),
+		referent_map,
+		string_table,
+	)?;
+	write_variant(
+		buffer,
+		&Variant::Ref(instance.parent()),
+		referent_map,
+		string_table,
 	)?;
 
 	match instance.class.as_str() {
@@ -597,79 +1231,382 @@ fn encode_instance<'dom>(
 		_ => {}
 	}
 
-	for (property, value) in &instance.properties {
-		if instance.class == "ModuleScript"
-			&& property == "Source"
-			&& options
-				.generation_requirements
-				.contains(Requirements::USE_NOVEL_INLINING)
-			&& let Variant::String(source) = value
-		{
-			// this won't panic because we write the instance ref before the loop and calling write_variant
-			// on a Ref means the referent_map is always populated with a usize for the Ref
-			options
-				.module_script_sources
-				.insert(*referent_map.get(&instance.referent()).unwrap(), source);
+	// Properties
+	if options.format_flags.contains(FormatFlags::COLUMNAR_LAYOUT) {
+		// property values are written into columns once every instance record is written, see encode_columns
+		return Ok(());
+	}
+
+	if let Some(schema_index) = schema_index {
+		// encode_property borrows options mutably, so the (cheap to copy) property names are cloned out of the schema
+		let property_names = options.schemas.as_ref().unwrap().schemas[schema_index]
+			.1
+			.clone();
 
-			write_nullstring(buffer, b"Source")
-				.wrap_err("failed writing Source property as nullstring")?;
+		for property in property_names {
+			match options
+				.property_overrides
+				.encoded_property(instance, property)
+			{
+				Some(value) => encode_property(instance, &property, value, options, buffer)
+					.wrap_err_with(|| format!("failed encoding property {property}"))?,
+				None => buffer
+					.write_all(&[TypeId::None as u8])
+					.wrap_err("failed writing None type id for missing schema property")?,
+			}
+		}
 
-			buffer
-				.write_all(&[TypeId::None as u8])
-				.wrap_err("failed writing None type id for null Source (for novel inlining)")?;
+		return Ok(());
+	}
 
-			continue;
+	let properties = options.property_overrides.encoded_properties(instance);
+	buffer.write_all(
+		&(u16::try_from(properties.len()).wrap_err("failed truncating properties length to u16")?)
+			.to_le_bytes(),
+	)?;
+
+	for (property, value) in properties {
+		write_interned_string(buffer, &property, &mut options.string_table)
+			.wrap_err("failed writing property name as interned string")?;
+		encode_property(instance, &property, value, options, buffer)
+			.wrap_err_with(|| format!("failed encoding property {property}"))?;
+	}
+
+	Ok(())
+}
+
+/// Writes a [`RecordKind::Clone`] record, which rebuilds the subtree of `instance` by cloning the (earlier encoded) subtree of `source`.
+fn encode_clone(
+	weak_dom: &WeakDom,
+	instance: &Instance,
+	source: Ref,
+	options: &mut Options,
+	buffer: &mut impl Write,
+) -> eyre::Result<()> {
+	let referent_map = &mut options.referent_map;
+	let string_table = &mut options.string_table;
+
+	buffer
+		.write_all(&[RecordKind::Clone as u8])
+		.wrap_err("failed writing record kind")?;
+	write_varstring(buffer, instance.name.as_bytes())?;
+
+	for referent in [source, instance.referent(), instance.parent()] {
+		write_variant(buffer, &Variant::Ref(referent), referent_map, string_table)?;
+	}
+
+	// other instances can point to the cloned descendants, so they need referents too
+	let descendants = &subtree_in_encoding_order(weak_dom, instance.referent())[1..];
+	leb128::write::unsigned(buffer, descendants.len().try_into()?)
+		.wrap_err("failed writing clone descendant count as leb128 encoded unsigned integer")?;
+
+	for descendant in descendants {
+		write_variant(
+			buffer,
+			&Variant::Ref(descendant.referent()),
+			referent_map,
+			string_table,
+		)?;
+	}
+
+	Ok(())
+}
+
+/// Writes a column from the encoded values (type id and value) of one property for every instance of a schema.
+///
+/// Values which share a [`TypeId`] with a [`TypeId::word_count`] are transposed, every other column is written as is.
+fn write_column(target: &mut impl Write, values: &[Vec<u8>], shuffle: bool) -> eyre::Result<()> {
+	let word_count = values.first().and_then(|first| {
+		let word_count = TypeId::try_from(first[0]).ok()?.word_count()?;
+
+		values
+			.iter()
+			.all(|value| value[0] == first[0] && value.len() == 1 + word_count * 4)
+			.then_some(word_count)
+	});
+
+	let Some(word_count) = word_count else {
+		target
+			.write_all(&[ColumnKind::Variants as u8])
+			.wrap_err("failed writing column kind")?;
+
+		for value in values {
+			target
+				.write_all(value)
+				.wrap_err("failed writing column variant")?;
 		}
 
-		options
-			.known_needed_type_ids
-			.extend(crate::spec::variant_to_type_id(value));
+		return Ok(());
+	};
+
+	let kind = if shuffle {
+		ColumnKind::ShuffledWords
+	} else {
+		ColumnKind::Words
+	};
+
+	target
+		.write_all(&[kind as u8, values[0][0]])
+		.wrap_err("failed writing column kind and type id")?;
+
+	// every first word, then every second word...
+	let words = (0..word_count)
+		.flat_map(|word| values.iter().map(move |value| &value[1 + word * 4..][..4]))
+		.collect::<Vec<_>>();
 
-		write_nullstring(buffer, property.as_bytes())
-			.wrap_err("failed writing property name as nullstring")?;
-		write_variant(buffer, value, referent_map).wrap_err("failed writing property variant")?;
+	if shuffle {
+		for byte in 0..4 {
+			target
+				.write_all(&words.iter().map(|word| word[byte]).collect::<Vec<_>>())
+				.wrap_err("failed writing shuffled column bytes")?;
+		}
+	} else {
+		target
+			.write_all(&words.concat())
+			.wrap_err("failed writing column words")?;
 	}
 
 	Ok(())
 }
 
+/// Writes one column per schema property, holding that property's value for every instance of the schema (in encoding order).
+fn encode_columns<'dom>(
+	instances_by_schema: &[Vec<&'dom Instance>],
+	options: &mut Options<'dom>,
+	buffer: &mut impl Write,
+) -> eyre::Result<()> {
+	let shuffle = options
+		.generation_requirements
+		.contains(Requirements::BYTE_SHUFFLE);
+
+	// encode_property borrows options mutably, so the schemas are put back once every column is written
+	let schemas = options.schemas.take().unwrap();
+
+	for ((_, property_names), instances) in schemas.schemas.iter().zip(instances_by_schema) {
+		for property in property_names {
+			let mut values = Vec::with_capacity(instances.len());
+
+			for instance in instances {
+				let mut value = Vec::new();
+				match options
+					.property_overrides
+					.encoded_property(instance, *property)
+				{
+					Some(variant) => encode_property(instance, property, variant, options, &mut value)
+						.wrap_err_with(|| format!("failed encoding property {property}"))?,
+					None => value.push(TypeId::None as u8),
+				}
+
+				values.push(value);
+			}
+
+			write_column(buffer, &values, shuffle)
+				.wrap_err_with(|| format!("failed writing column for property {property}"))?;
+		}
+	}
+
+	options.schemas = Some(schemas);
+
+	Ok(())
+}
+
+/// Sets the IMPLICIT requirements which depend on the type ids in the payload, once everything is encoded.
+pub(crate) fn add_type_id_requirements(options: &mut Options) {
+	// This should be here rather than encode_instance to avoid performance penalties
+	// as setting the same property in a loop is usually not a good idea
+	if options.known_needed_type_ids.contains(&TypeId::CFrame) {
+		options.generation_requirements |= Requirements::CFRAME_LOOKUP_TABLE;
+	}
+
+	// same reason as above
+	if options
+		.known_needed_type_ids
+		.contains(&TypeId::ContentObject)
+	{
+		options.generation_requirements |= Requirements::CONTENT_OBJECT_SUPPORT;
+	}
+}
+
+/// Writes the magic bytes ([`FORMAT_MAGIC`], or [`crate::spec::PATCH_MAGIC`] for patches), [`FORMAT_VERSION`] and [`FormatFlags`]
+/// which start every payload.
+pub(crate) fn write_header(
+	target: &mut impl Write,
+	magic: [u8; 6],
+	flags: FormatFlags,
+) -> eyre::Result<()> {
+	target
+		.write_all(&magic)
+		.wrap_err("failed writing magic bytes")?;
+	target
+		.write_all(&[FORMAT_VERSION])
+		.wrap_err("failed writing format version")?;
+	target
+		.write_all(&flags.bits().to_le_bytes())
+		.wrap_err("failed writing format flags")?;
+
+	Ok(())
+}
+
 /// Encodes a [`WeakDom`] into a writer that implements the [`Write`] trait.
 /// You should be passing a base [`Requirements`] with explicit fields set if you want them.
+///
+/// If [`Requirements::DROP_DEFAULT_PROPERTIES`] is set, defaults are read from [`DefaultProperties::from_local_or_bundled`].
+/// Use [`encode_dom_into_writer_with_defaults`] to customize them.
+///
+/// If [`Requirements::SKIP_UNWRITABLE_PROPERTIES`] is set, scriptability is read from [`ScriptableProperties::from_local_or_bundled`],
+/// and the skipped properties are reported in [`Options::unwritable_properties`].
 pub fn encode_dom_into_writer(
 	weak_dom: &'_ WeakDom,
-	mut writer: impl Write,
+	writer: impl Write,
 	base_requirements: Requirements,
 ) -> eyre::Result<Options<'_>> {
+	if base_requirements.contains(Requirements::DROP_DEFAULT_PROPERTIES) {
+		let default_properties = DefaultProperties::from_local_or_bundled()?;
+		return encode_dom(
+			weak_dom,
+			writer,
+			base_requirements,
+			Some(&default_properties),
+		);
+	}
+
+	encode_dom(weak_dom, writer, base_requirements, None)
+}
+
+/// Like [`encode_dom_into_writer`], but properties which equal their default in `default_properties` are always left out of the payload.
+pub fn encode_dom_into_writer_with_defaults<'dom>(
+	weak_dom: &'dom WeakDom,
+	writer: impl Write,
+	base_requirements: Requirements,
+	default_properties: &DefaultProperties,
+) -> eyre::Result<Options<'dom>> {
+	encode_dom(
+		weak_dom,
+		writer,
+		base_requirements | Requirements::DROP_DEFAULT_PROPERTIES,
+		Some(default_properties),
+	)
+}
+
+fn encode_dom<'dom>(
+	weak_dom: &'dom WeakDom,
+	mut writer: impl Write,
+	base_requirements: Requirements,
+	default_properties: Option<&DefaultProperties>,
+) -> eyre::Result<Options<'dom>> {
+	// the columnar layout is built on top of property schemas
+	let base_requirements = if base_requirements.contains(Requirements::COLUMNAR_LAYOUT) {
+		base_requirements | Requirements::PROPERTY_SCHEMAS
+	} else {
+		base_requirements
+	};
+
+	let scriptable_properties = base_requirements
+		.contains(Requirements::SKIP_UNWRITABLE_PROPERTIES)
+		.then(ScriptableProperties::from_local_or_bundled)
+		.transpose()?;
+	let (property_overrides, unwritable_properties) =
+		PropertyOverrides::collect(weak_dom, default_properties, scriptable_properties.as_ref());
+
+	let clones = if base_requirements.contains(Requirements::DEDUPLICATE_SUBTREES) {
+		find_subtree_clones(weak_dom, &property_overrides)
+	} else {
+		HashMap::new()
+	};
+
+	let blob_table = if base_requirements.contains(Requirements::BLOB_TABLE) {
+		BlobTable::from_dom(weak_dom, &property_overrides, &clones, base_requirements)
+	} else {
+		BlobTable::default()
+	};
+
 	let mut options = Options {
 		generation_requirements: base_requirements,
+		format_flags: FormatFlags::from_requirements(base_requirements),
 		known_needed_type_ids: HashSet::from([TypeId::String, TypeId::Ref, TypeId::None]),
 		module_script_sources: HashMap::new(),
 		referent_map: HashMap::new(),
+		string_table: StringTable::default(),
+		schemas: base_requirements
+			.contains(Requirements::PROPERTY_SCHEMAS)
+			.then(|| SchemaTable::from_dom(weak_dom, &property_overrides, &clones)),
+		blob_table,
+		property_overrides,
+		unwritable_properties,
 	};
 
+	// the string table precedes the schemas and instances, but it is only complete once both are encoded
+	let mut schemas = Vec::new();
+	if let Some(schema_table) = &options.schemas {
+		schema_table
+			.write_into(&mut schemas, &mut options.string_table)
+			.wrap_err("failed writing schema table")?;
+	}
+
+	let mut instances = Vec::new();
+
+	let columnar = options.format_flags.contains(FormatFlags::COLUMNAR_LAYOUT);
+	let mut instances_by_schema: Vec<Vec<&Instance>> = Vec::new();
+	let mut record_count = 0usize;
+
 	// we use a non-recursive DFS to avoid stack overflows
 	let mut stack = vec![weak_dom.root().referent()];
 	while let Some(instance_referent) = stack.pop() {
 		// children()'s contract states: "All referents returned will be non-null and point to valid instances in the same `WeakDom`".
 		let instance = weak_dom.get_by_ref(instance_referent).unwrap();
-		encode_instance(instance, &mut options, writer.by_ref())?;
+		record_count += 1;
 
-		stack.extend(instance.children().into_iter().rev().copied());
+		if let Some(&source) = clones.get(&instance_referent) {
+			encode_clone(weak_dom, instance, source, &mut options, &mut instances)
+				.wrap_err_with(|| format!("failed encoding clone of {}", instance.name))?;
+			continue;
+		}
+
+		encode_instance(instance, &mut options, &mut instances)?;
+
+		if columnar {
+			let schema_index = options.schemas.as_ref().unwrap().indices[&instance.class];
+			if instances_by_schema.len() <= schema_index {
+				instances_by_schema.resize_with(schema_index + 1, Vec::new);
+			}
+
+			instances_by_schema[schema_index].push(instance);
+		}
+
+		stack.extend(instance.children().iter().rev().copied());
 	}
 
-	// This should be here rather than encode_instance to avoid performance penalties
-	// as setting the same property in a loop is usually not a good idea
-	if options.known_needed_type_ids.contains(&TypeId::CFrame) {
-		options.generation_requirements |= Requirements::CFRAME_LOOKUP_TABLE;
+	if columnar {
+		// decoders need to know where the instance records end
+		let mut records = Vec::new();
+		leb128::write::unsigned(&mut records, record_count.try_into()?)
+			.wrap_err("failed writing instance count as leb128 encoded unsigned integer")?;
+		records.append(&mut instances);
+		instances = records;
+
+		encode_columns(&instances_by_schema, &mut options, &mut instances)
+			.wrap_err("failed encoding property columns")?;
 	}
 
-	// same reason as above
-	if options
-		.known_needed_type_ids
-		.contains(&TypeId::ContentObject)
-	{
-		options.generation_requirements |= Requirements::CONTENT_OBJECT_SUPPORT;
+	write_header(writer.by_ref(), FORMAT_MAGIC, options.format_flags)?;
+	options
+		.string_table
+		.write_into(writer.by_ref())
+		.wrap_err("failed writing string table")?;
+	if options.format_flags.contains(FormatFlags::BLOB_TABLE) {
+		options
+			.blob_table
+			.write_into(writer.by_ref())
+			.wrap_err("failed writing blob table")?;
 	}
+	writer
+		.write_all(&schemas)
+		.wrap_err("failed writing encoded schemas")?;
+	writer
+		.write_all(&instances)
+		.wrap_err("failed writing encoded instances")?;
+
+	add_type_id_requirements(&mut options);
 
 	Ok(options)
 }
@@ -0,0 +1,846 @@
+//! Azalea's script extraction logic
+//!
+//! Scripts generated by [`crate::emit::generate_full_script`] and [`crate::emit::generate_embeddable_script`]
+//! embed their model as a Base122 string literal passed into `DecompressBuffer` (or assigned directly, when uncompressed).
+//! Extraction finds that literal, undoes the Base122 encoding (with any [`Alphabet`]) and the compression,
+//! and hands back the raw payload.
+
+use crate::base122::Alphabet;
+use crate::spec::{FORMAT_MAGIC, PATCH_MAGIC};
+use color_eyre::eyre::{self, WrapErr, bail, ensure, eyre};
+use std::io::Read;
+
+/// The call which wraps the embedded payload in generated scripts, see [`crate::emit`].
+const PAYLOAD_CALL: &str = "DecompressBuffer(";
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// Unescapes the contents of a quoted Lua string (without the quotes).
+fn unescape_quoted_string(contents: &[u8]) -> eyre::Result<Vec<u8>> {
+	let mut output = Vec::with_capacity(contents.len());
+	let mut index = 0;
+
+	while index < contents.len() {
+		let byte = contents[index];
+		index += 1;
+
+		if byte != b'\\' {
+			output.push(byte);
+			continue;
+		}
+
+		let escape = *contents
+			.get(index)
+			.ok_or_else(|| eyre!("unfinished escape sequence at the end of the string"))?;
+		index += 1;
+
+		match escape {
+			b'a' => output.push(0x07),
+			b'b' => output.push(0x08),
+			b'f' => output.push(0x0C),
+			b'n' | b'\n' => output.push(b'\n'),
+			b'r' => output.push(b'\r'),
+			b't' => output.push(b'\t'),
+			b'v' => output.push(0x0B),
+			b'\\' | b'"' | b'\'' => output.push(escape),
+			b'\r' => {
+				// "\\\r\n" is a single escaped line break
+				if contents.get(index) == Some(&b'\n') {
+					index += 1;
+				}
+				output.push(b'\n');
+			}
+			b'z' => {
+				while contents
+					.get(index)
+					.is_some_and(|byte| byte.is_ascii_whitespace())
+				{
+					index += 1;
+				}
+			}
+			b'x' => {
+				let digits = contents
+					.get(index..index + 2)
+					.ok_or_else(|| eyre!("unfinished \\x escape sequence"))?;
+				output.push(
+					u8::from_str_radix(std::str::from_utf8(digits)?, 16)
+						.wrap_err("invalid \\x escape sequence")?,
+				);
+				index += 2;
+			}
+			b'u' => {
+				ensure!(
+					contents.get(index) == Some(&b'{'),
+					"expected {{ after \\u escape"
+				);
+				let end = contents[index..]
+					.iter()
+					.position(|&byte| byte == b'}')
+					.ok_or_else(|| eyre!("unfinished \\u escape sequence"))?
+					+ index;
+
+				let codepoint = u32::from_str_radix(std::str::from_utf8(&contents[index + 1..end])?, 16)
+					.wrap_err("invalid \\u escape sequence")?;
+				let character =
+					char::from_u32(codepoint).ok_or_else(|| eyre!("invalid codepoint {codepoint:x}"))?;
+
+				output.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes());
+				index = end + 1;
+			}
+			b'0'..=b'9' => {
+				let mut value = u32::from(escape - b'0');
+				for _ in 0..2 {
+					match contents.get(index) {
+						Some(digit @ b'0'..=b'9') => {
+							value = value * 10 + u32::from(digit - b'0');
+							index += 1;
+						}
+						_ => break,
+					}
+				}
+
+				output.push(u8::try_from(value).wrap_err("decimal escape sequence is too large")?);
+			}
+			_ => bail!("invalid escape sequence \\{}", escape as char),
+		}
+	}
+
+	Ok(output)
+}
+
+/// Parses the Lua string literal at the start of `source`, returning its contents and where it ends.
+fn parse_string_literal(source: &[u8]) -> eyre::Result<(Vec<u8>, usize)> {
+	match source.first() {
+		Some(&quote @ (b'"' | b'\'')) => {
+			let mut index = 1;
+			while index < source.len() {
+				match source[index] {
+					b'\\' => index += 2,
+					byte if byte == quote => {
+						return Ok((unescape_quoted_string(&source[1..index])?, index + 1));
+					}
+					_ => index += 1,
+				}
+			}
+
+			bail!("unfinished string literal")
+		}
+		Some(b'[') => {
+			let level = source[1..].iter().take_while(|&&byte| byte == b'=').count();
+
+			ensure!(
+				source.get(level + 1) == Some(&b'['),
+				"invalid long bracket string literal"
+			);
+
+			let mut closing = vec![b']'];
+			closing.extend(std::iter::repeat_n(b'=', level));
+			closing.push(b']');
+
+			let mut contents = &source[level + 2..];
+			let end = contents
+				.windows(closing.len())
+				.position(|window| window == closing)
+				.ok_or_else(|| eyre!("unfinished long bracket string literal"))?;
+
+			// a newline directly after the opening long bracket is skipped by Lua
+			contents = &contents[..end];
+			if let Some(stripped) = contents.strip_prefix(b"\r\n") {
+				contents = stripped;
+			} else if let Some(stripped) = contents.strip_prefix(b"\n") {
+				contents = stripped;
+			}
+
+			Ok((contents.to_vec(), level + 2 + end + closing.len()))
+		}
+		_ => bail!("expected a string literal"),
+	}
+}
+
+/// A string literal embedded into a generated script.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EmbeddedLiteral {
+	/// The raw (still encoded) contents of the literal.
+	pub contents: Vec<u8>,
+	/// The length of the data, which is passed to the base decoder after dense encoded literals (see [`crate::dense`]).
+	pub length: Option<usize>,
+}
+
+/// Parses the string literal at the start of `source`, and the data length which may be passed after it.
+fn parse_embedded_literal(source: &[u8]) -> eyre::Result<EmbeddedLiteral> {
+	let (contents, end) = parse_string_literal(source)?;
+
+	let rest = source[end..].trim_ascii_start();
+	let length = rest.strip_prefix(b",").and_then(|rest| {
+		let rest = rest.trim_ascii_start();
+		let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+
+		std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()
+	});
+
+	Ok(EmbeddedLiteral { contents, length })
+}
+
+/// Parses the last quoted string literal in `source`, which uncompressed payloads are embedded as.
+fn parse_last_quoted_literal(source: &str) -> eyre::Result<EmbeddedLiteral> {
+	let end = source
+		.rfind(['"', '\''])
+		.ok_or_else(|| eyre!("script does not contain an embedded payload"))?;
+	let quote = &source[end..=end];
+
+	// the opening quote is the closest one before which isn't escaped
+	let mut start = end;
+	loop {
+		start = source[..start]
+			.rfind(quote)
+			.ok_or_else(|| eyre!("unfinished string literal"))?;
+
+		let backslashes = source[..start]
+			.bytes()
+			.rev()
+			.take_while(|&byte| byte == b'\\')
+			.count();
+		if backslashes % 2 == 0 {
+			break;
+		}
+	}
+
+	parse_embedded_literal(&source.as_bytes()[start..])
+}
+
+/// Finds the last long bracket string literal in `source`, which uncompressed payloads with [`Alphabet::LongBracket`]
+/// are embedded as. Returns where it starts, and where its closing long bracket starts.
+fn find_last_long_bracket_literal(source: &str) -> Option<(usize, usize)> {
+	// the last closing long bracket, such as ]==]
+	let (closing_start, level) = source.rmatch_indices(']').find_map(|(end, _)| {
+		let level = source[..end]
+			.bytes()
+			.rev()
+			.take_while(|&byte| byte == b'=')
+			.count();
+
+		source[..end - level]
+			.ends_with(']')
+			.then(|| (end - level - 1, level))
+	})?;
+
+	let opening = format!("[{}[", "=".repeat(level));
+	let closing = format!("]{}]", "=".repeat(level));
+
+	// the opening long bracket is the first one which closes there
+	let start = source[..closing_start]
+		.match_indices(&opening)
+		.map(|(start, _)| start)
+		.find(|&start| {
+			source[start + opening.len()..]
+				.find(&closing)
+				.is_some_and(|end| start + opening.len() + end == closing_start)
+		})?;
+
+	Some((start, closing_start))
+}
+
+/// Parses whichever string literal ends last in `source`, quoted or long bracket.
+fn parse_last_literal(source: &str) -> eyre::Result<EmbeddedLiteral> {
+	let last_quote = source.rfind(['"', '\'']);
+
+	match find_last_long_bracket_literal(source) {
+		Some((start, closing_start)) if last_quote.is_none_or(|quote| quote < closing_start) => {
+			parse_embedded_literal(&source.as_bytes()[start..])
+		}
+		_ => parse_last_quoted_literal(source),
+	}
+}
+
+/// Finds the Base122 (or dense encoded) string literal embedded into a generated script.
+///
+/// The literal is expected to be the argument of the base decoder call inside of the last `DecompressBuffer(...)`,
+/// as decoders embedding a zstd fallback (see [`crate::emit::Requirements::ZSTD_FALLBACK`]) call it themselves.
+/// Minifiers may rename the base decoder and drop the call parentheses, both of which are handled.
+/// Uncompressed payloads (see [`crate::emit::Compression::None`]) aren't wrapped in a call, and are the last string literal instead.
+pub fn find_embedded_literal(source: &str) -> eyre::Result<EmbeddedLiteral> {
+	find_decompressed_literal(source).or_else(|error| {
+		parse_last_literal(source)
+			.ok()
+			.filter(|literal| !literal.contents.is_empty())
+			.ok_or(error)
+	})
+}
+
+fn find_decompressed_literal(source: &str) -> eyre::Result<EmbeddedLiteral> {
+	let start = source
+		.rfind(PAYLOAD_CALL)
+		.ok_or_else(|| eyre!("script does not contain an embedded payload"))?
+		+ PAYLOAD_CALL.len();
+
+	let mut rest = source[start..].trim_start();
+
+	// skip the base decoder's identifier
+	let identifier_length = rest
+		.find(|character: char| !(character.is_ascii_alphanumeric() || character == '_'))
+		.unwrap_or(rest.len());
+	ensure!(
+		identifier_length > 0,
+		"expected the base decoder to be called inside of {PAYLOAD_CALL}...)"
+	);
+	rest = rest[identifier_length..].trim_start();
+
+	if let Some(stripped) = rest.strip_prefix('(') {
+		rest = stripped.trim_start();
+	}
+
+	parse_embedded_literal(rest.as_bytes())
+}
+
+/// Largest payload [`decompress_lz_payload`] decompresses, which is as large as a Luau buffer can be.
+const MAX_LZ_PAYLOAD_SIZE: usize = 1 << 30;
+
+/// Decompresses a payload compressed with any [`crate::emit::Compression`] but [`crate::emit::Compression::Lz`],
+/// telling them apart by their magic bytes. Uncompressed payloads (and patches) are returned as they are,
+/// as is anything else, which [`crate::decoder::decode_dom`] then rejects for its missing magic header.
+///
+/// Payloads compressed with a zstd dictionary need [`decompress_payload_with_dictionary`] instead,
+/// and azalea's LZ has no magic bytes to tell it apart by, see [`decompress_lz_payload`].
+pub fn decompress_payload(data: &[u8]) -> eyre::Result<Vec<u8>> {
+	decompress_payload_with_dictionary(data, None)
+}
+
+/// Like [`decompress_payload`], but zstd compressed payloads are decompressed with `dictionary` (if it is given).
+pub fn decompress_payload_with_dictionary(
+	data: &[u8],
+	dictionary: Option<&[u8]>,
+) -> eyre::Result<Vec<u8>> {
+	const LZ4_FRAME_MAGIC: [u8; 4] = [0x04, 0x22, 0x4D, 0x18];
+
+	if data.starts_with(&ZSTD_MAGIC) {
+		let mut output = Vec::new();
+		zstd::Decoder::with_dictionary(data, dictionary.unwrap_or_default())
+			.and_then(|mut decoder| decoder.read_to_end(&mut output))
+			.wrap_err("failed decompressing zstd payload")?;
+
+		return Ok(output);
+	}
+
+	if data.starts_with(&LZ4_FRAME_MAGIC) {
+		let mut output = Vec::new();
+		lz4_flex::frame::FrameDecoder::new(data)
+			.read_to_end(&mut output)
+			.wrap_err("failed decompressing lz4 payload")?;
+
+		return Ok(output);
+	}
+
+	Ok(data.to_vec())
+}
+
+/// Decompresses a payload compressed with [`crate::emit::Compression::Lz`]: a u32 decompressed length (of at most
+/// a Luau buffer's size), followed by a single LZ4 block. Only call this for data which is known to be LZ compressed,
+/// as nothing else tells it apart.
+pub fn decompress_lz_payload(data: &[u8]) -> eyre::Result<Vec<u8>> {
+	let (length, block) = data
+		.split_first_chunk::<4>()
+		.ok_or_else(|| eyre!("lz payload is missing its decompressed length"))?;
+
+	let length = u32::from_le_bytes(*length) as usize;
+	ensure!(
+		length <= MAX_LZ_PAYLOAD_SIZE,
+		"lz payload claims to decompress into {length} bytes, more than the {MAX_LZ_PAYLOAD_SIZE} bytes a buffer can hold"
+	);
+
+	lz4_flex::block::decompress(block, length)
+		.map_err(|e| eyre!("failed decompressing lz payload: {e}"))
+}
+
+/// Returns whether the payload of a script is passed to a Luau decompressor (the zstd fallback, a dictionary runtime
+/// or azalea's LZ decompressor, see [`crate::emit`]), rather than EncodingService, which is told the algorithm.
+fn decompressed_in_luau(source: &str) -> bool {
+	source
+		.rfind(PAYLOAD_CALL)
+		.is_some_and(|start| !source[start..].contains("Enum.CompressionAlgorithm"))
+}
+
+/// Decompresses data extracted from a script. The only Luau decompressors besides azalea's LZ decompress zstd,
+/// so compressed data passed to one without zstd's magic bytes is LZ compressed.
+fn decompress_extracted_payload(
+	data: &[u8],
+	dictionary: Option<&[u8]>,
+	decompressed_in_luau: bool,
+) -> eyre::Result<Vec<u8>> {
+	let is_uncompressed = data.starts_with(&FORMAT_MAGIC) || data.starts_with(&PATCH_MAGIC);
+	if decompressed_in_luau && !is_uncompressed && !data.starts_with(&ZSTD_MAGIC) {
+		return decompress_lz_payload(data);
+	}
+
+	decompress_payload_with_dictionary(data, dictionary)
+}
+
+/// Extracts the uncompressed payload embedded into a generated script.
+///
+/// The returned payload can be decoded with [`crate::decoder::decode_dom`].
+pub fn extract_payload_from_script(source: &str) -> eyre::Result<Vec<u8>> {
+	extract_payload_from_script_with_dictionary(source, None)
+}
+
+/// Like [`extract_payload_from_script`], for scripts compressed with a zstd dictionary
+/// (see [`crate::emit::Compression::ZstdDictionary`]).
+pub fn extract_payload_from_script_with_dictionary(
+	source: &str,
+	dictionary: Option<&[u8]>,
+) -> eyre::Result<Vec<u8>> {
+	let literal = find_embedded_literal(source)?;
+	let decompressed_in_luau = decompressed_in_luau(source);
+
+	if let Some(length) = literal.length {
+		let compressed = crate::dense::dense_decode(&literal.contents, length)
+			.map_err(|e| eyre!("failed decoding embedded dense data: {e}"))?;
+
+		return decompress_extracted_payload(&compressed, dictionary, decompressed_in_luau)
+			.wrap_err("failed decompressing embedded payload");
+	}
+
+	let alphabets = match detect_alphabet(source) {
+		Some(alphabet) => vec![alphabet],
+		None => Alphabet::ALL.to_vec(),
+	};
+
+	// prefer reporting why data which decoded didn't decompress
+	let mut decompression_error = None;
+	let mut decoding_error = None;
+	for alphabet in alphabets {
+		match alphabet.decode(&literal.contents) {
+			Ok(compressed) => {
+				match decompress_extracted_payload(&compressed, dictionary, decompressed_in_luau) {
+					Ok(payload) => return Ok(payload),
+					Err(error) => {
+						decompression_error
+							.get_or_insert(error.wrap_err("failed decompressing embedded payload"));
+					}
+				}
+			}
+			Err(error) => {
+				decoding_error.get_or_insert(eyre!(
+					"failed decoding embedded {alphabet:?} Base122 data: {error}"
+				));
+			}
+		}
+	}
+
+	Err(decompression_error.or(decoding_error).unwrap())
+}
+
+/// Finds which alphabet a script was generated with, from the illegal bytes table of its base decoder
+/// (see [`crate::base122::generate_luau_decoder`]). Formatters may add whitespace to the table, or requote the literal itself.
+fn detect_alphabet(source: &str) -> Option<Alphabet> {
+	let compact: String = source
+		.chars()
+		.filter(|character| !character.is_whitespace())
+		.collect();
+
+	Alphabet::ALL
+		.into_iter()
+		.filter_map(|alphabet| {
+			let table = alphabet
+				.illegal_bytes()
+				.iter()
+				.map(u8::to_string)
+				.collect::<Vec<_>>()
+				.join(",");
+
+			compact
+				.rfind(&format!("table.freeze({{{table}}})"))
+				.map(|position| (position, alphabet))
+		})
+		.max_by_key(|(position, _)| *position)
+		.map(|(_, alphabet)| alphabet)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::base122::Alphabet;
+	use crate::emit::{
+		Compression, PayloadEncoding, Requirements, ScriptKind, ZstdParameters,
+		generate_embeddable_script, generate_multipart_script, generate_smallest_script,
+	};
+	use rbx_dom_weak::{InstanceBuilder, WeakDom};
+
+	#[test]
+	fn extract_from_generated_script() {
+		let weak_dom =
+			rbx_binary::from_reader(std::fs::File::open("examples/attributes-and-tags.rbxm").unwrap())
+				.unwrap();
+
+		let mut script = Vec::new();
+		generate_embeddable_script(
+			&weak_dom,
+			Requirements::OPENSB_SUPPORT,
+			Compression::default(),
+			PayloadEncoding::default(),
+			&mut script,
+		);
+
+		let mut expected = Vec::new();
+		crate::encoder::encode_dom_into_writer(&weak_dom, &mut expected, Requirements::empty())
+			.unwrap();
+
+		let payload = extract_payload_from_script(std::str::from_utf8(&script).unwrap()).unwrap();
+		assert_eq!(payload, expected);
+	}
+
+	#[test]
+	fn extract_from_script_with_zstd_fallback() {
+		let weak_dom =
+			rbx_binary::from_reader(std::fs::File::open("examples/attributes-and-tags.rbxm").unwrap())
+				.unwrap();
+
+		let mut script = Vec::new();
+		generate_embeddable_script(
+			&weak_dom,
+			Requirements::OPENSB_SUPPORT | Requirements::ZSTD_FALLBACK,
+			Compression::default(),
+			PayloadEncoding::default(),
+			&mut script,
+		);
+		let script = std::str::from_utf8(&script).unwrap();
+		assert!(script.contains("local function decompressZstd("));
+
+		let mut expected = Vec::new();
+		crate::encoder::encode_dom_into_writer(&weak_dom, &mut expected, Requirements::empty())
+			.unwrap();
+
+		assert_eq!(extract_payload_from_script(script).unwrap(), expected);
+	}
+
+	#[test]
+	fn extract_with_every_compression() {
+		let weak_dom =
+			rbx_binary::from_reader(std::fs::File::open("examples/attributes-and-tags.rbxm").unwrap())
+				.unwrap();
+
+		let mut expected = Vec::new();
+		crate::encoder::encode_dom_into_writer(&weak_dom, &mut expected, Requirements::empty())
+			.unwrap();
+
+		for compression in [
+			Compression::None,
+			Compression::Zstd(ZstdParameters::level(1)),
+			Compression::Lz4,
+			Compression::Lz,
+		] {
+			// the zstd fallback's own DecompressBuffer call mustn't be mistaken for the payload's
+			let mut script = Vec::new();
+			let options = generate_embeddable_script(
+				&weak_dom,
+				Requirements::OPENSB_SUPPORT | Requirements::ZSTD_FALLBACK,
+				compression,
+				PayloadEncoding::default(),
+				&mut script,
+			);
+			let script = std::str::from_utf8(&script).unwrap();
+
+			assert_eq!(
+				options
+					.generation_requirements
+					.contains(Requirements::LZ_DECOMPRESSOR),
+				compression == Compression::Lz
+			);
+			assert_eq!(
+				extract_payload_from_script(script).unwrap(),
+				expected,
+				"{compression:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn extract_dense_payloads() {
+		let weak_dom =
+			rbx_binary::from_reader(std::fs::File::open("examples/attributes-and-tags.rbxm").unwrap())
+				.unwrap();
+
+		let mut expected = Vec::new();
+		crate::encoder::encode_dom_into_writer(&weak_dom, &mut expected, Requirements::empty())
+			.unwrap();
+
+		for compression in [
+			Compression::None,
+			Compression::Zstd(ZstdParameters::level(1)),
+			Compression::Lz,
+		] {
+			let mut script = Vec::new();
+			generate_embeddable_script(
+				&weak_dom,
+				Requirements::OPENSB_SUPPORT,
+				compression,
+				PayloadEncoding::Dense,
+				&mut script,
+			);
+			let script = std::str::from_utf8(&script).unwrap();
+
+			assert_eq!(
+				extract_payload_from_script(script).unwrap(),
+				expected,
+				"{compression:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn reassemble_multipart_payloads() {
+		let weak_dom =
+			rbx_binary::from_reader(std::fs::File::open("examples/fusion-0.3-release.rbxm").unwrap())
+				.unwrap();
+
+		let mut expected = Vec::new();
+		crate::encoder::encode_dom_into_writer(&weak_dom, &mut expected, Requirements::empty())
+			.unwrap();
+
+		let encodings = Alphabet::ALL
+			.into_iter()
+			.map(PayloadEncoding::from)
+			.chain([PayloadEncoding::Dense]);
+		for payload_encoding in encodings {
+			for compression in [
+				Compression::None,
+				Compression::Zstd(ZstdParameters::level(1)),
+			] {
+				let (_, script) = generate_multipart_script(
+					&weak_dom,
+					Requirements::OPENSB_SUPPORT,
+					ScriptKind::Embeddable,
+					compression,
+					payload_encoding,
+					4096,
+				)
+				.unwrap();
+				assert!(script.parts.len() > 1);
+				assert!(script.loader.contains("gatherPayloadParts()"));
+
+				let mut compressed = Vec::new();
+				for part in &script.parts {
+					assert!(part.len() <= 4096, "{} > 4096", part.len());
+
+					let literal = find_embedded_literal(part).unwrap();
+					compressed.extend(match (payload_encoding, literal.length) {
+						(PayloadEncoding::Base122(alphabet), None) => {
+							alphabet.decode(&literal.contents).unwrap()
+						}
+						(PayloadEncoding::Dense, Some(length)) => {
+							crate::dense::dense_decode(&literal.contents, length).unwrap()
+						}
+						_ => panic!("{payload_encoding:?} part has the wrong arguments"),
+					});
+				}
+
+				assert_eq!(
+					decompress_payload(&compressed).unwrap(),
+					expected,
+					"{payload_encoding:?} {compression:?}"
+				);
+			}
+		}
+
+		let tiny = generate_multipart_script(
+			&weak_dom,
+			Requirements::OPENSB_SUPPORT,
+			ScriptKind::Embeddable,
+			Compression::default(),
+			PayloadEncoding::default(),
+			64,
+		);
+		assert!(tiny.is_err());
+
+		let mut folder_root = WeakDom::new(InstanceBuilder::new("DataModel"));
+		folder_root.insert(folder_root.root_ref(), InstanceBuilder::new("Folder"));
+		let not_module_script = generate_multipart_script(
+			&folder_root,
+			Requirements::OPENSB_SUPPORT,
+			ScriptKind::Full,
+			Compression::default(),
+			PayloadEncoding::default(),
+			4096,
+		);
+		assert!(not_module_script.is_err());
+	}
+
+	#[test]
+	fn extract_with_every_alphabet() {
+		let weak_dom =
+			rbx_binary::from_reader(std::fs::File::open("examples/attributes-and-tags.rbxm").unwrap())
+				.unwrap();
+
+		let mut expected = Vec::new();
+		crate::encoder::encode_dom_into_writer(&weak_dom, &mut expected, Requirements::empty())
+			.unwrap();
+
+		for alphabet in Alphabet::ALL {
+			for compression in [
+				Compression::None,
+				Compression::Zstd(ZstdParameters::level(1)),
+			] {
+				let mut script = Vec::new();
+				generate_embeddable_script(
+					&weak_dom,
+					Requirements::OPENSB_SUPPORT,
+					compression,
+					alphabet.into(),
+					&mut script,
+				);
+				let script = std::str::from_utf8(&script).unwrap();
+
+				let (opening, closing) = alphabet.delimiters();
+				assert!(script.contains(&format!("f({opening}")));
+				assert!(script.contains(&format!("{closing})")));
+				assert_eq!(
+					extract_payload_from_script(script).unwrap(),
+					expected,
+					"{alphabet:?} {compression:?}"
+				);
+
+				// without the base decoder's table, every alphabet is tried
+				let table = alphabet
+					.illegal_bytes()
+					.iter()
+					.map(u8::to_string)
+					.collect::<Vec<_>>()
+					.join(",");
+				let script = script.replace(&format!("table.freeze({{{table}}})"), "illegalBytes");
+				assert_eq!(detect_alphabet(&script), None);
+				if compression != Compression::None {
+					assert_eq!(
+						extract_payload_from_script(&script).unwrap(),
+						expected,
+						"{alphabet:?} {compression:?}"
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn extract_with_dictionary() {
+		let weak_dom =
+			rbx_binary::from_reader(std::fs::File::open("examples/attributes-and-tags.rbxm").unwrap())
+				.unwrap();
+
+		let mut expected = Vec::new();
+		crate::encoder::encode_dom_into_writer(&weak_dom, &mut expected, Requirements::empty())
+			.unwrap();
+
+		// raw content dictionaries have no ID, so they are registered by their contents
+		let dictionary = expected.clone();
+		let mut script = Vec::new();
+		generate_embeddable_script(
+			&weak_dom,
+			Requirements::OPENSB_SUPPORT,
+			Compression::ZstdDictionary {
+				parameters: ZstdParameters::level(3),
+				dictionary: &dictionary,
+			},
+			PayloadEncoding::default(),
+			&mut script,
+		);
+		let script = std::str::from_utf8(&script).unwrap();
+		let runtime = crate::emit::generate_dictionary_runtime(&dictionary);
+		let key = runtime
+			.lines()
+			.find_map(|line| line.strip_prefix("local DICTIONARY_KEY = "))
+			.unwrap()
+			.trim_matches('"');
+		assert!(script.contains(&format!("shared.AzaleaZstdDictionaries['{key}']")));
+
+		let other_runtime = crate::emit::generate_dictionary_runtime(&dictionary[1..]);
+		assert!(!other_runtime.contains(key));
+
+		assert_eq!(
+			extract_payload_from_script_with_dictionary(script, Some(&dictionary)).unwrap(),
+			expected
+		);
+		assert!(extract_payload_from_script(script).is_err());
+	}
+
+	#[test]
+	fn extract_smallest_script() {
+		let weak_dom =
+			rbx_binary::from_reader(std::fs::File::open("examples/attributes-and-tags.rbxm").unwrap())
+				.unwrap();
+
+		let mut script = Vec::new();
+		let (_, trials) = generate_smallest_script(
+			&weak_dom,
+			Requirements::OPENSB_SUPPORT,
+			ScriptKind::Embeddable,
+			11,
+			None,
+			PayloadEncoding::default(),
+			&mut script,
+		);
+
+		let smallest = trials.iter().map(|trial| trial.script_length).min();
+		assert_eq!(smallest, Some(script.len()));
+		assert_eq!(trials[0].compression, Compression::default());
+
+		let payload = extract_payload_from_script(std::str::from_utf8(&script).unwrap()).unwrap();
+		assert!(payload.starts_with(&FORMAT_MAGIC));
+	}
+
+	#[test]
+	fn unknown_payloads_pass_through() {
+		let garbage = b"not a payload at all".to_vec();
+		assert_eq!(decompress_payload(&garbage).unwrap(), garbage);
+
+		let error = crate::decoder::decode_dom(&garbage).unwrap_err();
+		assert!(
+			format!("{error:?}").contains("missing magic header"),
+			"{error:?}"
+		);
+
+		let mut oversized = u32::MAX.to_le_bytes().to_vec();
+		oversized.extend_from_slice(&garbage);
+		assert!(decompress_lz_payload(&oversized).is_err());
+		assert!(decompress_lz_payload(&[1, 0]).is_err());
+
+		let compressed = lz4_flex::block::compress_prepend_size(&garbage);
+		assert_eq!(decompress_lz_payload(&compressed).unwrap(), garbage);
+	}
+
+	#[test]
+	fn parse_escaped_literals() {
+		assert_eq!(
+			parse_string_literal(br#""a\65\x42\u{e9}\"\\""#).unwrap(),
+			("aAB\u{e9}\"\\".as_bytes().to_vec(), 20)
+		);
+		assert_eq!(
+			parse_string_literal(b"'it\\'s',1").unwrap(),
+			(b"it's".to_vec(), 7)
+		);
+		assert_eq!(
+			parse_string_literal(b"[==[\nx]]y]==])").unwrap(),
+			(b"x]]y".to_vec(), 13)
+		);
+	}
+
+	#[test]
+	fn find_minified_literal() {
+		assert_eq!(
+			find_embedded_literal("local a=b:DecompressBuffer(c\"xyz\",d)").unwrap(),
+			EmbeddedLiteral {
+				contents: b"xyz".to_vec(),
+				length: None
+			}
+		);
+		assert_eq!(
+			find_embedded_literal("local a=b'x\\'y\\\\'return c(a)")
+				.unwrap()
+				.contents,
+			b"x'y\\"
+		);
+		assert_eq!(
+			find_embedded_literal("local a=b:DecompressBuffer(c([=[\n]]x]=], 3))").unwrap(),
+			EmbeddedLiteral {
+				contents: b"]]x".to_vec(),
+				length: Some(3)
+			}
+		);
+	}
+}
@@ -2,9 +2,18 @@
 //!
 //! Currently, it is most useful when used to embed models in environments that forbid `require(id)`.
 
+pub mod decoder;
+pub mod diff;
 pub mod emit;
 pub mod encoder;
+pub mod reflection;
 pub mod spec;
 
 #[cfg(feature = "base122")]
 pub mod base122;
+#[cfg(feature = "base122")]
+pub mod dense;
+#[cfg(feature = "base122")]
+pub mod extract;
+#[cfg(feature = "serve")]
+pub mod serve;
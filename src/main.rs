@@ -1,4 +1,7 @@
-use azalea::emit::Requirements;
+use azalea::base122::Alphabet;
+use azalea::emit::{
+	Compression, PayloadEncoding, Requirements, ScriptKind, SizeTrial, ZstdParameters,
+};
 use azalea::encoder::encode_dom_into_writer;
 use clap::{Parser, Subcommand, value_parser};
 use color_eyre::eyre::{self, Context, bail, ensure, eyre};
@@ -9,7 +12,10 @@ use darklua_core::rules::{
 use darklua_core::{Configuration, GeneratorParameters, Options, Resources};
 use rbx_dom_weak::WeakDom;
 use std::io::{BufWriter, Write};
+use std::net::TcpListener;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use std::{fs::File, io::BufReader};
 
 #[derive(Subcommand)]
@@ -19,6 +25,9 @@ enum Command {
 		#[clap(flatten)]
 		options: GenerateOptions,
 
+		#[clap(flatten)]
+		encoding_options: EncodingOptions,
+
 		/// Optional output location for a specialized decoder designed for the input model(s)
 		#[arg(short, long)]
 		specialized_decoder: Option<PathBuf>,
@@ -32,8 +41,19 @@ enum Command {
 		#[clap(flatten)]
 		requirement_options: RequirementOptions,
 
+		#[clap(flatten)]
+		encoding_options: EncodingOptions,
+
 		#[clap(flatten)]
 		compression_options: CompressionOptions,
+
+		/// Try zstd levels, long distance matching, window sizes and payload layouts, keeping the smallest script; sizes are compared before --format, --minify or --compat, which may change the ranking
+		#[arg(long)]
+		optimize_size: bool,
+
+		/// Split the payload across part scripts of at most this many bytes, written next to the output as <name>.part<N>.luau; they must run before it
+		#[arg(long, conflicts_with = "optimize_size")]
+		part_size: Option<usize>,
 	},
 
 	/// Fully encodes a model file into an embeddable script, with optional formatting, minification and compat available.
@@ -44,19 +64,186 @@ enum Command {
 		#[clap(flatten)]
 		requirement_options: RequirementOptions,
 
+		#[clap(flatten)]
+		encoding_options: EncodingOptions,
+
 		#[clap(flatten)]
 		compression_options: CompressionOptions,
+
+		/// Try zstd levels, long distance matching, window sizes and payload layouts, keeping the smallest script; sizes are compared before --format, --minify or --compat, which may change the ranking
+		#[arg(long)]
+		optimize_size: bool,
+
+		/// Split the payload across part scripts of at most this many bytes, written next to the output as <name>.part<N>.luau; they must run before it
+		#[arg(long, conflicts_with = "optimize_size")]
+		part_size: Option<usize>,
 	},
 
 	/// Generates the full decoder into a file, with optional formatting, minification and compat available.
-	GenerateFullDecoder { output: PathBuf },
+	GenerateFullDecoder {
+		output: PathBuf,
+
+		/// Whether the decoder should also hold decodeAsync, which decodes in time slices (see --yielding)
+		#[arg(long, default_value_t = false)]
+		yielding: bool,
+
+		/// Whether to collect properties which fail to apply into a report, which decode returns next to the root (for debugging)
+		#[arg(long, default_value_t = false)]
+		property_error_report: bool,
+	},
+
+	/// Generates a patch script (or a raw .bin patch) which updates a tree decoded from the old model file into the new model file in place.
+	Diff {
+		/// Model file (.rbxm, .rbxmx) the live tree was decoded from
+		old: PathBuf,
+
+		/// Model file (.rbxm, .rbxmx) the live tree should be updated to
+		new: PathBuf,
+
+		/// Output luau file, or .bin file for the raw patch
+		#[arg(short, long)]
+		output: PathBuf,
+
+		#[clap(flatten)]
+		requirement_options: RequirementOptions,
+
+		#[clap(flatten)]
+		compression_options: CompressionOptions,
+	},
+
+	/// Serves model file(s) to a live sync client script, which decodes them and applies their changes as patches.
+	Serve {
+		/// Input model file(s) (.rbxm, .rbxmx) to watch
+		#[arg(short, long = "input", num_args = 1.., required = true)]
+		inputs: Vec<PathBuf>,
+
+		/// Address to listen on
+		#[arg(long, default_value = "127.0.0.1:34873")]
+		address: String,
+
+		/// Optional output location for the client script, which is also served at /client.luau
+		#[arg(long)]
+		client: Option<PathBuf>,
+
+		#[clap(flatten)]
+		encoding_options: EncodingOptions,
+
+		#[clap(flatten)]
+		compression_options: CompressionOptions,
+	},
+
+	/// Trains a zstd dictionary on payload file(s) or model file(s), which small models compress much better with (see --dictionary).
+	TrainDictionary {
+		/// Input payload file(s) (.bin, optionally compressed) or model file(s) (.rbxm, .rbxmx); the more, the better
+		#[arg(short, long = "input", num_args = 1.., required = true)]
+		inputs: Vec<PathBuf>,
+
+		/// Output dictionary file
+		#[arg(short, long)]
+		output: PathBuf,
+
+		/// Maximum size of the dictionary in bytes
+		#[arg(long, default_value_t = 16384)]
+		size: usize,
+
+		/// Encoding options for model file inputs, which should match the ones the dictionary is used with
+		#[clap(flatten)]
+		encoding_options: EncodingOptions,
+	},
+
+	/// Generates the dictionary runtime for a dictionary, which must run before any script compressed with the dictionary.
+	GenerateDictionaryRuntime {
+		/// Dictionary file (from train-dictionary)
+		dictionary: PathBuf,
+
+		/// Output luau file
+		#[arg(short, long)]
+		output: PathBuf,
+	},
+
+	/// Decode payload file(s) (.bin, optionally compressed) back into model file(s), or uncompressed payload file(s) (.bin).
+	Decode {
+		#[clap(flatten)]
+		options: DecodeOptions,
+	},
+
+	/// Extract the embedded model from generated script(s) (.luau) into model or payload file(s) (.rbxm, .rbxmx, .bin).
+	Extract {
+		#[clap(flatten)]
+		options: ExtractOptions,
+	},
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ModelFormat {
+	Rbxm,
+	Rbxmx,
+	/// Raw (uncompressed) azalea payload
+	Bin,
+}
+
+impl ModelFormat {
+	const fn extension(self) -> &'static str {
+		match self {
+			Self::Rbxm => "rbxm",
+			Self::Rbxmx => "rbxmx",
+			Self::Bin => "bin",
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum CompressionAlgorithm {
+	/// Embed the payload uncompressed, for tiny models
+	None,
+	/// Zstandard, decompressed by EncodingService
+	Zstd,
+	/// LZ4, decompressed by EncodingService
+	Lz4,
+	/// LZ4 blocks decompressed by a small Luau decompressor in the script, for environments without EncodingService
+	Lz,
 }
 
 #[derive(clap::Args)]
 struct CompressionOptions {
+	/// Compression algorithm for embedded model file
+	#[arg(long, value_enum, default_value_t = CompressionAlgorithm::Zstd)]
+	compression: CompressionAlgorithm,
+
 	/// Zstandard compression level for embedded model file; 1 to 22 (slowest)
 	#[arg(short, long, value_parser = value_parser!(u8).range(1..=22), default_value_t = 11)]
 	level: u8,
+
+	/// Zstandard dictionary (from train-dictionary) to compress with; scripts then need its dictionary runtime
+	#[arg(long)]
+	dictionary: Option<PathBuf>,
+
+	/// Kind of string literal (and matching encoding) the payload is embedded as
+	#[arg(long, value_enum, default_value_t = PayloadAlphabet::DoubleQuoted)]
+	alphabet: PayloadAlphabet,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum PayloadAlphabet {
+	/// Base123 in a double quoted string
+	DoubleQuoted,
+	/// Base123 in a single quoted string
+	SingleQuoted,
+	/// Base122 without closing brackets in a long bracket string
+	LongBracket,
+	/// Azalea's dense encoding in a long bracket string, about 1.25% smaller than Base123
+	Dense,
+}
+
+impl From<PayloadAlphabet> for PayloadEncoding {
+	fn from(alphabet: PayloadAlphabet) -> Self {
+		match alphabet {
+			PayloadAlphabet::DoubleQuoted => Alphabet::DoubleQuoted.into(),
+			PayloadAlphabet::SingleQuoted => Alphabet::SingleQuoted.into(),
+			PayloadAlphabet::LongBracket => Alphabet::LongBracket.into(),
+			PayloadAlphabet::Dense => Self::Dense,
+		}
+	}
 }
 
 #[derive(clap::Args)]
@@ -70,6 +257,48 @@ struct GenerateOptions {
 	output: PathBuf,
 }
 
+#[derive(clap::Args)]
+struct DecodeOptions {
+	/// Input payload file(s) (.bin, optionally compressed)
+	#[arg(short, long = "input", num_args = 1.., required = true)]
+	inputs: Vec<PathBuf>,
+
+	/// Output model or payload file (.rbxm, .rbxmx, .bin) / directory
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Dictionary the payload(s) were compressed with
+	#[arg(long)]
+	dictionary: Option<PathBuf>,
+
+	/// The payload(s) were compressed with --compression lz, which has no magic bytes to detect it by
+	#[arg(long, conflicts_with = "dictionary")]
+	lz: bool,
+
+	/// Model format used for outputs when decoding into a directory; single outputs use their file extension
+	#[arg(long, value_enum, default_value_t = ModelFormat::Rbxm)]
+	model_format: ModelFormat,
+}
+
+#[derive(clap::Args)]
+struct ExtractOptions {
+	/// Input generated script(s) (.luau)
+	#[arg(short, long = "input", num_args = 1.., required = true)]
+	inputs: Vec<PathBuf>,
+
+	/// Output model or payload file (.rbxm, .rbxmx, .bin) / directory
+	#[arg(short, long)]
+	output: PathBuf,
+
+	/// Dictionary the script(s) were compressed with
+	#[arg(long)]
+	dictionary: Option<PathBuf>,
+
+	/// Model format used for outputs when extracting into a directory; single outputs use their file extension
+	#[arg(long, value_enum, default_value_t = ModelFormat::Rbxm)]
+	model_format: ModelFormat,
+}
+
 #[derive(clap::Args)]
 struct RequirementOptions {
 	/// Whether to support legacy environments or not
@@ -87,6 +316,49 @@ struct RequirementOptions {
 	/// Whether to use the Novel method which completely inlines ModuleScript sources. Overrides OpenSB's NewModuleScript support entirely.
 	#[arg(long = "novel", default_value_t = false)]
 	novel: bool,
+
+	/// Whether to embed a pure-Luau zstd decompressor, which is used when EncodingService is unavailable
+	#[arg(long, default_value_t = false)]
+	zstd_fallback: bool,
+
+	/// Whether to decode in time slices, yielding between them (with task.wait) so huge models don't time out the script
+	#[arg(long, default_value_t = false)]
+	yielding: bool,
+
+	/// Whether to collect properties which fail to apply into a report, and warn a summary of it (for debugging)
+	#[arg(long, default_value_t = false)]
+	property_error_report: bool,
+}
+
+#[derive(clap::Args)]
+struct EncodingOptions {
+	/// Write the property names of every class once, instead of in every instance
+	#[arg(long, default_value_t = false)]
+	schemas: bool,
+
+	/// Group property values by property instead of by instance (implies --schemas); usually compresses better
+	#[arg(long, default_value_t = false)]
+	columnar: bool,
+
+	/// Shuffle the bytes of float columns so similar bytes sit together (requires --columnar)
+	#[arg(long, default_value_t = false, requires = "columnar")]
+	shuffle: bool,
+
+	/// Leave out properties which equal their default (uses the local reflection database if present, set RBX_DATABASE to override)
+	#[arg(long, default_value_t = false)]
+	drop_defaults: bool,
+
+	/// Leave out properties which scripts can't write (the decoder would fail setting them), and report them
+	#[arg(long, default_value_t = false)]
+	skip_unwritable: bool,
+
+	/// Encode repeated subtrees (such as identical parts or UI list items) as clones of their first occurrence
+	#[arg(long, default_value_t = false)]
+	dedupe: bool,
+
+	/// Write large strings which repeat (such as the Source of vendored packages) once, into a table which properties reference
+	#[arg(long, default_value_t = false)]
+	blob_table: bool,
 }
 
 #[derive(clap::Args)]
@@ -132,7 +404,15 @@ fn read_dom_from_path<T: AsRef<Path>>(path: T) -> eyre::Result<WeakDom> {
 		File::open(path).with_context(|| format!("failed opening path {}", path.display()))?,
 	);
 
-	let extension = path
+	Ok(match get_extension(path)? {
+		"rbxm" => rbx_binary::from_reader(file)?,
+		"rbxmx" => rbx_xml::from_reader_default(file)?,
+		_ => bail!("invalid file extension"),
+	})
+}
+
+fn get_extension(path: &Path) -> eyre::Result<&str> {
+	path
 		.extension()
 		.ok_or_else(|| eyre!("file {} has no extension", path.display()))?
 		.to_str()
@@ -141,13 +421,73 @@ fn read_dom_from_path<T: AsRef<Path>>(path: T) -> eyre::Result<WeakDom> {
 				"failed &OsStr to &str conversion for path {}",
 				path.display()
 			)
-		})?;
+		})
+}
 
-	Ok(match extension {
-		"rbxm" => rbx_binary::from_reader(file)?,
-		"rbxmx" => rbx_xml::from_reader_default(file)?,
-		_ => bail!("invalid file extension"),
-	})
+fn write_dom_to_path<T: AsRef<Path>>(path: T, weak_dom: &WeakDom) -> eyre::Result<()> {
+	let path = path.as_ref();
+	let extension = get_extension(path)?;
+	ensure!(
+		matches!(extension, "rbxm" | "rbxmx"),
+		"invalid file extension {extension} for model file {}",
+		path.display()
+	);
+
+	let mut file = BufWriter::new(
+		File::create(path).with_context(|| format!("failed creating path {}", path.display()))?,
+	);
+
+	// the payload root is the DataModel, the model file should only contain it's children
+	let roots = weak_dom.root().children();
+
+	if extension == "rbxm" {
+		rbx_binary::to_writer(&mut file, weak_dom, roots)?;
+	} else {
+		rbx_xml::to_writer_default(&mut file, weak_dom, roots)?;
+	}
+
+	file.flush()?;
+
+	Ok(())
+}
+
+/// Reads a payload from a path, decompressing it (with `dictionary`, if it needs one) if it is compressed.
+/// LZ compressed payloads can't be detected, so `lz` says whether the payload is one.
+fn read_payload_from_path<T: AsRef<Path>>(
+	path: T,
+	dictionary: Option<&[u8]>,
+	lz: bool,
+) -> eyre::Result<Vec<u8>> {
+	let path = path.as_ref();
+	let payload =
+		std::fs::read(path).with_context(|| format!("failed reading path {}", path.display()))?;
+
+	if lz {
+		azalea::extract::decompress_lz_payload(&payload)
+	} else {
+		azalea::extract::decompress_payload_with_dictionary(&payload, dictionary)
+	}
+	.with_context(|| format!("failed decompressing payload {}", path.display()))
+}
+
+/// Decodes the payload file at `input` into a model file (or an uncompressed payload file) at `output`.
+fn decode_payload_file(
+	input: &Path,
+	output: &Path,
+	dictionary: Option<&[u8]>,
+	lz: bool,
+) -> eyre::Result<()> {
+	let payload = read_payload_from_path(input, dictionary, lz)?;
+	let weak_dom = azalea::decoder::decode_dom(&payload)
+		.with_context(|| format!("failed decoding payload {}", input.display()))?;
+
+	// decoding into a payload file only decompresses it, once it is known to decode
+	if get_extension(output)? == "bin" {
+		return std::fs::write(output, payload)
+			.with_context(|| format!("failed writing payload to {}", output.display()));
+	}
+
+	write_dom_to_path(output, &weak_dom)
 }
 
 #[must_use]
@@ -241,7 +581,7 @@ fn write_to_luau_file<T: AsRef<Path>>(
 	Ok(())
 }
 
-fn write_with_callback<T: AsRef<Path>, F: FnOnce(WeakDom, &mut Vec<u8>)>(
+fn write_with_callback<T: AsRef<Path>, F: FnOnce(WeakDom, &mut Vec<u8>) -> eyre::Result<()>>(
 	input: T,
 	output: T,
 	callback: F,
@@ -251,7 +591,7 @@ fn write_with_callback<T: AsRef<Path>, F: FnOnce(WeakDom, &mut Vec<u8>)>(
 ) -> eyre::Result<()> {
 	let dom = read_dom_from_path(input)?;
 	let mut src: Vec<u8> = Vec::new();
-	callback(dom, &mut src);
+	callback(dom, &mut src)?;
 	write_to_luau_file(
 		output,
 		// Base122 (and by extension, Base123) encoded data is valid UTF-8.
@@ -265,6 +605,137 @@ fn write_with_callback<T: AsRef<Path>, F: FnOnce(WeakDom, &mut Vec<u8>)>(
 	Ok(())
 }
 
+/// Writes the parts of a multi-part script next to its loader at `output`, as they are (formatting or minifying them
+/// could make them larger than --part-size).
+fn write_parts(output: &Path, parts: &[String]) -> eyre::Result<()> {
+	let stem = output
+		.file_stem()
+		.ok_or_else(|| eyre!("output {} doesn't have a file name", output.display()))?
+		.to_str()
+		.ok_or_else(|| eyre!("output {} has a invalid utf-8 file name", output.display()))?;
+
+	for (index, part) in parts.iter().enumerate() {
+		let path = output.with_file_name(format!("{stem}.part{}.luau", index + 1));
+		std::fs::write(&path, part)
+			.with_context(|| format!("failed writing payload part to {}", path.display()))?;
+	}
+
+	if !parts.is_empty() {
+		eprintln!(
+			"{}: split the payload across {} part(s), which must run before it",
+			output.display(),
+			parts.len()
+		);
+	}
+
+	Ok(())
+}
+
+/// Tells the user which properties were left out of (or renamed in) the payload of `input`, see `--skip-unwritable`.
+fn report_unwritable_properties(input: &Path, options: &azalea::emit::Options) {
+	for property in &options.unwritable_properties {
+		match property.encoded_as {
+			Some(encoded_as) => eprintln!(
+				"{}: encoded unwritable property {}.{} as {encoded_as} ({} instances)",
+				input.display(),
+				property.class,
+				property.property,
+				property.instance_count
+			),
+			None => eprintln!(
+				"{}: left out unwritable property {}.{} ({} instances)",
+				input.display(),
+				property.class,
+				property.property,
+				property.instance_count
+			),
+		}
+	}
+}
+
+/// Tells the user what `--optimize-size` tried for `input`, and what it kept.
+fn report_size_trials(input: &Path, trials: &[SizeTrial]) {
+	let describe = |trial: &SizeTrial| {
+		let layout = if trial.layout.is_empty() {
+			"default layout".to_string()
+		} else {
+			trial
+				.layout
+				.iter_names()
+				.map(|(name, _)| name.to_lowercase().replace('_', "-"))
+				.collect::<Vec<_>>()
+				.join("+")
+		};
+
+		let (Compression::Zstd(parameters) | Compression::ZstdDictionary { parameters, .. }) =
+			trial.compression
+		else {
+			unreachable!("--optimize-size only tries zstd")
+		};
+
+		let mut description = format!("{layout}, zstd level {}", parameters.level);
+		if parameters.long_distance_matching {
+			description.push_str(" with long distance matching");
+		}
+		if let Some(window_log) = parameters.window_log {
+			description.push_str(&format!(" (window 2^{window_log})"));
+		}
+
+		description
+	};
+
+	eprintln!(
+		"{}: tried {} configurations (sizes before formatting or minifying)",
+		input.display(),
+		trials.len()
+	);
+
+	let kept = trials
+		.iter()
+		.min_by_key(|trial| trial.script_length)
+		.expect("at least one configuration is tried");
+
+	for trial in trials {
+		eprintln!("  {:>9} bytes  {}", trial.script_length, describe(trial));
+	}
+
+	let baseline = trials[0].script_length;
+	eprintln!(
+		"{}: kept {} at {} bytes, {} bytes smaller than without --optimize-size",
+		input.display(),
+		describe(kept),
+		kept.script_length,
+		baseline - kept.script_length
+	);
+}
+
+/// Reads the dictionary at `path`, if there is one.
+fn read_dictionary(path: Option<&Path>) -> eyre::Result<Option<Vec<u8>>> {
+	path
+		.map(|path| {
+			std::fs::read(path).with_context(|| format!("failed reading dictionary {}", path.display()))
+		})
+		.transpose()
+}
+
+/// `dictionary` must be read from the options' dictionary path (see [`read_dictionary`]).
+fn get_compression_from_compression_options<'a>(
+	options: &CompressionOptions,
+	dictionary: Option<&'a [u8]>,
+) -> eyre::Result<Compression<'a>> {
+	Ok(match (options.compression, dictionary) {
+		(CompressionAlgorithm::Zstd, Some(dictionary)) => Compression::ZstdDictionary {
+			parameters: ZstdParameters::level(options.level),
+			dictionary,
+		},
+		(_, Some(_)) => bail!("dictionaries can only be used with zstd compression"),
+		(CompressionAlgorithm::None, None) => Compression::None,
+		(CompressionAlgorithm::Zstd, None) => Compression::Zstd(ZstdParameters::level(options.level)),
+		(CompressionAlgorithm::Lz4, None) => Compression::Lz4,
+		(CompressionAlgorithm::Lz, None) => Compression::Lz,
+	})
+}
+
 fn get_requirements_from_requirement_options(options: &RequirementOptions) -> Requirements {
 	let mut requirements = Requirements::empty();
 
@@ -284,6 +755,52 @@ fn get_requirements_from_requirement_options(options: &RequirementOptions) -> Re
 		requirements.insert(Requirements::USE_NOVEL_INLINING);
 	}
 
+	if options.zstd_fallback {
+		requirements.insert(Requirements::ZSTD_FALLBACK);
+	}
+
+	if options.yielding {
+		requirements.insert(Requirements::YIELDING_DECODER);
+	}
+
+	if options.property_error_report {
+		requirements.insert(Requirements::PROPERTY_ERROR_REPORT);
+	}
+
+	requirements
+}
+
+fn get_requirements_from_encoding_options(options: &EncodingOptions) -> Requirements {
+	let mut requirements = Requirements::empty();
+
+	if options.schemas {
+		requirements.insert(Requirements::PROPERTY_SCHEMAS);
+	}
+
+	if options.columnar {
+		requirements.insert(Requirements::COLUMNAR_LAYOUT);
+	}
+
+	if options.shuffle {
+		requirements.insert(Requirements::BYTE_SHUFFLE);
+	}
+
+	if options.drop_defaults {
+		requirements.insert(Requirements::DROP_DEFAULT_PROPERTIES);
+	}
+
+	if options.skip_unwritable {
+		requirements.insert(Requirements::SKIP_UNWRITABLE_PROPERTIES);
+	}
+
+	if options.dedupe {
+		requirements.insert(Requirements::DEDUPLICATE_SUBTREES);
+	}
+
+	if options.blob_table {
+		requirements.insert(Requirements::BLOB_TABLE);
+	}
+
 	requirements
 }
 
@@ -297,32 +814,76 @@ fn main() -> eyre::Result<()> {
 		args.global_options.compat,
 	);
 
+	ensure!(
+		!matches!(
+			args.command,
+			Command::Decode { .. } | Command::Extract { .. }
+		) || !(format || minify || compat),
+		"decode and extract write model and payload files, which can't be formatted, minified or made compatible"
+	);
+
 	// Vec<(input, output)>
 	let mut inputs = vec![];
 
 	let file_extension = match &args.command {
 		Command::Encode { .. } => "bin",
 		Command::GenerateFullScript { .. } | Command::GenerateEmbeddableScript { .. } => "luau",
-		Command::GenerateFullDecoder { .. } => "",
+		Command::GenerateFullDecoder { .. }
+		| Command::Diff { .. }
+		| Command::Serve { .. }
+		| Command::TrainDictionary { .. }
+		| Command::GenerateDictionaryRuntime { .. } => "",
+		Command::Decode {
+			options: DecodeOptions { model_format, .. },
+		}
+		| Command::Extract {
+			options: ExtractOptions { model_format, .. },
+		} => model_format.extension(),
 	};
 
 	// ensure single input -> single file, and multiple inputs -> single directory
 	match &args.command {
 		// commands which can take multiple inputs
-		Command::Encode { options, .. }
+		Command::Encode {
+			options: GenerateOptions {
+				inputs: input_paths,
+				output,
+			},
+			..
+		}
 		| Command::GenerateFullScript {
-			generate_options: options,
+			generate_options: GenerateOptions {
+				inputs: input_paths,
+				output,
+			},
 			..
 		}
 		| Command::GenerateEmbeddableScript {
-			generate_options: options,
+			generate_options: GenerateOptions {
+				inputs: input_paths,
+				output,
+			},
 			..
+		}
+		| Command::Decode {
+			options: DecodeOptions {
+				inputs: input_paths,
+				output,
+				..
+			},
+		}
+		| Command::Extract {
+			options: ExtractOptions {
+				inputs: input_paths,
+				output,
+				..
+			},
 		} => {
-			let metadata = std::fs::metadata(&options.output);
+			let metadata = std::fs::metadata(output);
 
-			inputs.reserve_exact(options.inputs.len());
+			inputs.reserve_exact(input_paths.len());
 
-			let is_single_file = options.inputs.len() == 1;
+			let is_single_file = input_paths.len() == 1;
 
 			if is_single_file {
 				if let Ok(metadata) = metadata {
@@ -332,10 +893,7 @@ fn main() -> eyre::Result<()> {
 					);
 				}
 
-				inputs.push((
-					options.inputs.first().unwrap().clone(),
-					options.output.clone(),
-				));
+				inputs.push((input_paths.first().unwrap().clone(), output.clone()));
 			} else {
 				ensure!(metadata.is_ok(), "output path does not exist");
 				ensure!(
@@ -343,7 +901,7 @@ fn main() -> eyre::Result<()> {
 					"output path is not a directory, but multiple inputs were passed"
 				);
 
-				for input in options.inputs.clone() {
+				for input in input_paths.clone() {
 					let file = format!(
 						"{}.{file_extension}",
 						input
@@ -353,28 +911,196 @@ fn main() -> eyre::Result<()> {
 							.ok_or_else(|| eyre!("input {} has a invalid utf-8 file name", input.display()))?
 					);
 
-					inputs.push((input, options.output.join(file)));
+					inputs.push((input, output.join(file)));
 				}
 			}
 		}
 
 		// only one output, exit here
-		Command::GenerateFullDecoder { output } => {
+		Command::GenerateFullDecoder {
+			output,
+			yielding,
+			property_error_report,
+		} => {
+			let mut requirements = Requirements::empty();
+			requirements.set(Requirements::YIELDING_DECODER, *yielding);
+			requirements.set(Requirements::PROPERTY_ERROR_REPORT, *property_error_report);
+
+			write_to_luau_file(
+				output,
+				azalea::emit::generate_full_decoder_with(requirements),
+				format,
+				minify,
+				compat,
+			)?;
+			return Ok(());
+		}
+
+		Command::TrainDictionary {
+			inputs,
+			output,
+			size,
+			encoding_options,
+		} => {
+			let mut samples = Vec::with_capacity(inputs.len());
+
+			for input in inputs {
+				if get_extension(input)? == "bin" {
+					samples.push(read_payload_from_path(input, None, false)?);
+					continue;
+				}
+
+				let mut payload = Vec::new();
+				encode_dom_into_writer(
+					&read_dom_from_path(input)?,
+					&mut payload,
+					get_requirements_from_encoding_options(encoding_options),
+				)
+				.with_context(|| format!("failed encoding {}", input.display()))?;
+				samples.push(payload);
+			}
+
+			let dictionary = zstd::dict::from_samples(&samples, *size).wrap_err(
+				"failed training dictionary, it needs more (or larger) inputs, or a smaller --size",
+			)?;
+			std::fs::write(output, &dictionary)
+				.with_context(|| format!("failed writing dictionary to {}", output.display()))?;
+
+			eprintln!(
+				"trained a {} byte dictionary on {} input(s)",
+				dictionary.len(),
+				samples.len()
+			);
+			return Ok(());
+		}
+
+		Command::GenerateDictionaryRuntime { dictionary, output } => {
+			let dictionary = std::fs::read(dictionary)
+				.with_context(|| format!("failed reading dictionary {}", dictionary.display()))?;
+
 			write_to_luau_file(
 				output,
-				azalea::emit::generate_full_decoder(),
+				azalea::emit::generate_dictionary_runtime(&dictionary),
 				format,
 				minify,
 				compat,
 			)?;
 			return Ok(());
 		}
+
+		Command::Diff {
+			old,
+			new,
+			output,
+			requirement_options,
+			compression_options,
+		} => {
+			let old_dom = read_dom_from_path(old)?;
+			let new_dom = read_dom_from_path(new)?;
+
+			if get_extension(output)? == "bin" {
+				let mut patch = Vec::new();
+				azalea::diff::diff_doms_into_writer(&old_dom, &new_dom, &mut patch, Requirements::empty())
+					.wrap_err("failed diffing models")?;
+				std::fs::write(output, patch)
+					.with_context(|| format!("failed writing patch to {}", output.display()))?;
+
+				return Ok(());
+			}
+
+			let dictionary = read_dictionary(compression_options.dictionary.as_deref())?;
+			let mut src = Vec::new();
+			azalea::emit::generate_patch_script(
+				&old_dom,
+				&new_dom,
+				get_requirements_from_requirement_options(requirement_options),
+				get_compression_from_compression_options(compression_options, dictionary.as_deref())?,
+				compression_options.alphabet.into(),
+				&mut src,
+			)
+			.wrap_err("failed generating patch script")?;
+
+			// Base122 (and by extension, Base123) encoded data is valid UTF-8.
+			write_to_luau_file(
+				output,
+				unsafe { String::from_utf8_unchecked(src) },
+				format,
+				minify,
+				compat,
+			)?;
+			return Ok(());
+		}
+
+		Command::Serve {
+			inputs,
+			address,
+			client,
+			encoding_options,
+			compression_options,
+		} => {
+			// the client script only carries a zstd decompressor
+			ensure!(
+				compression_options.compression == CompressionAlgorithm::Zstd
+					&& compression_options.dictionary.is_none()
+					&& compression_options.alphabet == PayloadAlphabet::DoubleQuoted,
+				"serve only supports zstd compression without a dictionary, in double quoted strings"
+			);
+
+			let server = Arc::new(
+				azalea::serve::Server::new(
+					inputs.clone(),
+					|path| read_dom_from_path(path),
+					get_requirements_from_encoding_options(encoding_options),
+					compression_options.level,
+				)
+				.wrap_err("failed loading models")?,
+			);
+
+			let listener =
+				TcpListener::bind(address).with_context(|| format!("failed listening on {address}"))?;
+			let url = format!("http://{}", listener.local_addr()?);
+
+			if let Some(client) = client {
+				write_to_luau_file(
+					client,
+					azalea::emit::generate_live_sync_client(&url, &server.model_names()),
+					format,
+					minify,
+					compat,
+				)?;
+			}
+
+			eprintln!(
+				"serving {} model(s) at {url}, the client script is at {url}/client.luau",
+				inputs.len()
+			);
+
+			std::thread::spawn({
+				let server = Arc::clone(&server);
+				move || {
+					loop {
+						std::thread::sleep(Duration::from_millis(250));
+
+						for (path, result) in server.refresh() {
+							match result {
+								Ok(version) => eprintln!("{}: updated to version {version}", path.display()),
+								Err(error) => eprintln!("{}: failed reloading: {error:?}", path.display()),
+							}
+						}
+					}
+				}
+			});
+
+			server.handle_requests(listener)?;
+			return Ok(());
+		}
 	}
 
 	let is_single_file = inputs.len() == 1;
 
 	match args.command {
 		Command::Encode {
+			encoding_options,
 			specialized_decoder,
 			..
 		} => {
@@ -389,10 +1115,13 @@ fn main() -> eyre::Result<()> {
 					Requirements::RETURN_DECODE
 						| Requirements::LEGACY_SUPPORT
 						| Requirements::OPENSB_SUPPORT
-						| Requirements::STUDIO_SUPPORT,
+						| Requirements::STUDIO_SUPPORT
+						| get_requirements_from_encoding_options(&encoding_options),
 				)
 				.with_context(|| format!("failed encoding dom into output path {}", output.display()))?;
 
+				report_unwritable_properties(&input, &options);
+
 				// "It is critical to call flush before BufWriter<W> is dropped." - BufWriter documentation
 				// basically, it's so you can catch errors here and not ignore the errors implicitly in drop.
 				output_writer.flush()?;
@@ -423,55 +1152,197 @@ fn main() -> eyre::Result<()> {
 
 		Command::GenerateFullScript {
 			requirement_options,
+			encoding_options,
 			compression_options,
+			optimize_size,
+			part_size,
 			..
 		} => {
+			ensure!(
+				!optimize_size || compression_options.compression == CompressionAlgorithm::Zstd,
+				"--optimize-size only tunes zstd compression"
+			);
+
+			let dictionary = read_dictionary(compression_options.dictionary.as_deref())?;
+			let compression =
+				get_compression_from_compression_options(&compression_options, dictionary.as_deref())?;
+
 			for (input, output) in inputs {
+				let mut parts = Vec::new();
 				write_with_callback(
 					&input,
 					&output,
 					|weak_dom, src| {
-						azalea::emit::generate_full_script(
-							&weak_dom,
-							get_requirements_from_requirement_options(&requirement_options),
-							compression_options.level,
-							src,
-						);
+						let requirements = get_requirements_from_requirement_options(&requirement_options)
+							| get_requirements_from_encoding_options(&encoding_options);
+						let options = if optimize_size {
+							let (options, trials) = azalea::emit::generate_smallest_script(
+								&weak_dom,
+								requirements,
+								ScriptKind::Full,
+								compression_options.level,
+								dictionary.as_deref(),
+								compression_options.alphabet.into(),
+								src,
+							);
+							report_size_trials(&input, &trials);
+							options
+						} else if let Some(part_size) = part_size {
+							let (options, script) = azalea::emit::generate_multipart_script(
+								&weak_dom,
+								requirements,
+								ScriptKind::Full,
+								compression,
+								compression_options.alphabet.into(),
+								part_size,
+							)
+							.wrap_err("failed generating multi-part script")?;
+							src.extend_from_slice(script.loader.as_bytes());
+							parts = script.parts;
+							options
+						} else {
+							azalea::emit::generate_full_script(
+								&weak_dom,
+								requirements,
+								compression,
+								compression_options.alphabet.into(),
+								src,
+							)
+						};
+						report_unwritable_properties(&input, &options);
+
+						Ok(())
 					},
 					format,
 					minify,
 					compat,
 				)
-				.expect("failed writing or generating full script");
+				.with_context(|| format!("failed generating full script from {}", input.display()))?;
+
+				write_parts(&output, &parts)?;
 			}
 		}
 
 		Command::GenerateEmbeddableScript {
 			requirement_options,
+			encoding_options,
 			compression_options,
+			optimize_size,
+			part_size,
 			..
 		} => {
+			ensure!(
+				!optimize_size || compression_options.compression == CompressionAlgorithm::Zstd,
+				"--optimize-size only tunes zstd compression"
+			);
+
+			let dictionary = read_dictionary(compression_options.dictionary.as_deref())?;
+			let compression =
+				get_compression_from_compression_options(&compression_options, dictionary.as_deref())?;
+
 			for (input, output) in inputs {
+				let mut parts = Vec::new();
 				write_with_callback(
 					&input,
 					&output,
 					|weak_dom, src| {
-						azalea::emit::generate_embeddable_script(
-							&weak_dom,
-							get_requirements_from_requirement_options(&requirement_options),
-							compression_options.level,
-							src,
-						);
+						let requirements = get_requirements_from_requirement_options(&requirement_options)
+							| get_requirements_from_encoding_options(&encoding_options);
+						let options = if optimize_size {
+							let (options, trials) = azalea::emit::generate_smallest_script(
+								&weak_dom,
+								requirements,
+								ScriptKind::Embeddable,
+								compression_options.level,
+								dictionary.as_deref(),
+								compression_options.alphabet.into(),
+								src,
+							);
+							report_size_trials(&input, &trials);
+							options
+						} else if let Some(part_size) = part_size {
+							let (options, script) = azalea::emit::generate_multipart_script(
+								&weak_dom,
+								requirements,
+								ScriptKind::Embeddable,
+								compression,
+								compression_options.alphabet.into(),
+								part_size,
+							)
+							.wrap_err("failed generating multi-part script")?;
+							src.extend_from_slice(script.loader.as_bytes());
+							parts = script.parts;
+							options
+						} else {
+							azalea::emit::generate_embeddable_script(
+								&weak_dom,
+								requirements,
+								compression,
+								compression_options.alphabet.into(),
+								src,
+							)
+						};
+						report_unwritable_properties(&input, &options);
+
+						Ok(())
 					},
 					format,
 					minify,
 					compat,
 				)
-				.expect("failed writing or generating full script");
+				.with_context(|| {
+					format!(
+						"failed generating embeddable script from {}",
+						input.display()
+					)
+				})?;
+
+				write_parts(&output, &parts)?;
 			}
 		}
 
-		Command::GenerateFullDecoder { .. } => {
+		Command::Decode {
+			options: DecodeOptions { dictionary, lz, .. },
+		} => {
+			let dictionary = read_dictionary(dictionary.as_deref())?;
+
+			for (input, output) in inputs {
+				decode_payload_file(&input, &output, dictionary.as_deref(), lz)?;
+			}
+		}
+
+		Command::Extract {
+			options: ExtractOptions { dictionary, .. },
+		} => {
+			let dictionary = read_dictionary(dictionary.as_deref())?;
+
+			for (input, output) in inputs {
+				let source = std::fs::read_to_string(&input)
+					.with_context(|| format!("failed reading script {}", input.display()))?;
+				let payload = azalea::extract::extract_payload_from_script_with_dictionary(
+					&source,
+					dictionary.as_deref(),
+				)
+				.with_context(|| format!("failed extracting payload from {}", input.display()))?;
+
+				if get_extension(&output)? == "bin" {
+					std::fs::write(&output, payload)
+						.with_context(|| format!("failed writing payload to {}", output.display()))?;
+					continue;
+				}
+
+				let weak_dom = azalea::decoder::decode_dom(&payload)
+					.with_context(|| format!("failed decoding payload extracted from {}", input.display()))?;
+
+				write_dom_to_path(&output, &weak_dom)?;
+			}
+		}
+
+		Command::GenerateFullDecoder { .. }
+		| Command::Diff { .. }
+		| Command::Serve { .. }
+		| Command::TrainDictionary { .. }
+		| Command::GenerateDictionaryRuntime { .. } => {
 			// this was already handled
 			unreachable!()
 		}
@@ -479,3 +1350,39 @@ fn main() -> eyre::Result<()> {
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn decode_compressed_payload_file() {
+		let weak_dom = read_dom_from_path("examples/attributes-and-tags.rbxm").unwrap();
+		let mut payload = Vec::new();
+		encode_dom_into_writer(&weak_dom, &mut payload, Requirements::empty()).unwrap();
+
+		let directory = std::env::temp_dir().join(format!("azalea-decode-{}", std::process::id()));
+		std::fs::create_dir_all(&directory).unwrap();
+
+		let input = directory.join("payload.bin");
+		std::fs::write(&input, zstd::encode_all(payload.as_slice(), 19).unwrap()).unwrap();
+
+		let model = directory.join("model.rbxm");
+		decode_payload_file(&input, &model, None, false).unwrap();
+		let decoded = read_dom_from_path(&model).unwrap();
+		assert_eq!(
+			decoded.descendants().count(),
+			weak_dom.descendants().count()
+		);
+
+		let uncompressed = directory.join("uncompressed.bin");
+		decode_payload_file(&input, &uncompressed, None, false).unwrap();
+		assert_eq!(std::fs::read(&uncompressed).unwrap(), payload);
+
+		let unknown = directory.join("model.txt");
+		assert!(decode_payload_file(&input, &unknown, None, false).is_err());
+		assert!(!unknown.exists());
+
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+}
@@ -0,0 +1,368 @@
+//! Azalea's reflection logic
+//!
+//! Payloads only need to contain the properties which `Instance.new` does not already set in the decoder.
+//! [`DefaultProperties`] compares property values against the defaults in a reflection database,
+//! see [`crate::emit::Requirements::DROP_DEFAULT_PROPERTIES`].
+//!
+//! Decoders also can't set properties which scripts can't write, [`ScriptableProperties`] finds them,
+//! see [`crate::emit::Requirements::SKIP_UNWRITABLE_PROPERTIES`].
+
+use color_eyre::eyre::{self, WrapErr};
+use rbx_dom_weak::types::Variant;
+use rbx_reflection::{PropertyDescriptor, PropertyKind, ReflectionDatabase, Scriptability};
+use std::collections::{HashMap, HashSet};
+
+/// Properties which decoders pass into the function creating an instance (such as `NewScript`), so they are always encoded.
+const CONSTRUCTOR_PROPERTIES: [&str; 2] = ["Source", "MeshContent"];
+
+/// Properties which decoders set through methods (`SetAttribute` and `AddTag`) instead of assigning them.
+const DECODER_PROPERTIES: [&str; 2] = ["Attributes", "Tags"];
+
+/// Decides which property values equal the value `Instance.new` gives them, so they can be left out of payloads.
+///
+/// Some environments change the defaults of classes, which can be accounted for with [`Self::keep_property`] and [`Self::set_default`].
+/// Both apply to the given class and every class which inherits from it, like `Instance:IsA(className)`.
+pub struct DefaultProperties<'database> {
+	database: &'database ReflectionDatabase<'database>,
+	kept_properties: HashMap<String, HashSet<String>>,
+	overridden_defaults: HashMap<String, HashMap<String, Variant>>,
+}
+
+impl DefaultProperties<'static> {
+	/// Uses the database from [`rbx_reflection_database::get`]; the local database (which can be set with the `RBX_DATABASE` environment variable)
+	/// if there is one, and the database bundled with azalea otherwise.
+	pub fn from_local_or_bundled() -> eyre::Result<Self> {
+		Ok(Self::new(
+			rbx_reflection_database::get().wrap_err("failed loading local reflection database")?,
+		))
+	}
+}
+
+impl<'database> DefaultProperties<'database> {
+	#[must_use]
+	pub fn new(database: &'database ReflectionDatabase<'database>) -> Self {
+		Self {
+			database,
+			kept_properties: HashMap::new(),
+			overridden_defaults: HashMap::new(),
+		}
+	}
+
+	/// Always encodes `property` on instances of `class`, even if it equals the default.
+	pub fn keep_property(&mut self, class: impl Into<String>, property: impl Into<String>) {
+		self
+			.kept_properties
+			.entry(class.into())
+			.or_default()
+			.insert(property.into());
+	}
+
+	/// Uses `default` instead of the reflection database's default for `property` on instances of `class`.
+	pub fn set_default(
+		&mut self,
+		class: impl Into<String>,
+		property: impl Into<String>,
+		default: Variant,
+	) {
+		self
+			.overridden_defaults
+			.entry(class.into())
+			.or_default()
+			.insert(property.into(), default);
+	}
+
+	/// Returns whether `value` is the default of `property` for instances of `class`, meaning it can be left out of a payload.
+	///
+	/// Properties of classes which are missing from the reflection database are never considered default.
+	#[must_use]
+	pub fn is_default(&self, class: &str, property: &str, value: &Variant) -> bool {
+		if CONSTRUCTOR_PROPERTIES.contains(&property) {
+			return false;
+		}
+
+		let Some(descriptor) = self.database.classes.get(class) else {
+			return false;
+		};
+
+		// the database lists inherited defaults on every class, so overrides have to be checked before any of them
+		for class in self.database.superclasses_iter(descriptor) {
+			let class = class.name.as_ref();
+
+			if self
+				.kept_properties
+				.get(class)
+				.is_some_and(|properties| properties.contains(property))
+			{
+				return false;
+			}
+
+			if let Some(default) = self
+				.overridden_defaults
+				.get(class)
+				.and_then(|defaults| defaults.get(property))
+			{
+				return default == value;
+			}
+		}
+
+		self
+			.database
+			.find_default_property(descriptor, property)
+			.is_some_and(|default| default == value)
+	}
+}
+
+/// How a decoder can set a property, see [`ScriptableProperties::access`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyAccess<'database> {
+	/// Scripts (or decoders themselves) can set the property.
+	Writable,
+	/// Scripts can't set the property, but it is a serialized-only alias of the given property, which they can set.
+	/// For example, `Fire.heat_xml` is an alias of `Fire.Heat`.
+	AliasOf(&'database str),
+	/// Scripts can't set the property, so decoders would fail setting it.
+	Unwritable,
+}
+
+/// Decides which properties scripts can set, using the scriptability of properties in a reflection database.
+pub struct ScriptableProperties<'database> {
+	database: &'database ReflectionDatabase<'database>,
+}
+
+impl ScriptableProperties<'static> {
+	/// Uses the database from [`rbx_reflection_database::get`], see [`DefaultProperties::from_local_or_bundled`].
+	pub fn from_local_or_bundled() -> eyre::Result<Self> {
+		Ok(Self::new(
+			rbx_reflection_database::get().wrap_err("failed loading local reflection database")?,
+		))
+	}
+}
+
+impl<'database> ScriptableProperties<'database> {
+	#[must_use]
+	pub fn new(database: &'database ReflectionDatabase<'database>) -> Self {
+		Self { database }
+	}
+
+	fn find_property(
+		&self,
+		class: &str,
+		property: &str,
+	) -> Option<&'database PropertyDescriptor<'database>> {
+		let descriptor = self.database.classes.get(class)?;
+
+		self
+			.database
+			.superclasses_iter(descriptor)
+			.find_map(|class| class.properties.get(property))
+	}
+
+	/// Returns how a decoder can set `property` to `value` on instances of `class`.
+	///
+	/// Every property of classes which are missing from the reflection database is considered [`PropertyAccess::Writable`],
+	/// while properties which are missing from a known class are [`PropertyAccess::Unwritable`].
+	#[must_use]
+	pub fn access(&self, class: &str, property: &str, value: &Variant) -> PropertyAccess<'database> {
+		if CONSTRUCTOR_PROPERTIES.contains(&property)
+			|| DECODER_PROPERTIES.contains(&property)
+			|| !self.database.classes.contains_key(class)
+		{
+			return PropertyAccess::Writable;
+		}
+
+		let Some(descriptor) = self.find_property(class, property) else {
+			return PropertyAccess::Unwritable;
+		};
+
+		if is_writable(descriptor) {
+			return PropertyAccess::Writable;
+		}
+
+		// aliases like AttributesSerialize (a BinaryString of Attributes) store values differently, so they are unwritable
+		if let PropertyKind::Alias { alias_for } = &descriptor.kind
+			&& let Some(canonical) = self.find_property(class, alias_for)
+			&& is_writable(canonical)
+			&& canonical.data_type.ty() == value.ty()
+		{
+			return PropertyAccess::AliasOf(alias_for);
+		}
+
+		PropertyAccess::Unwritable
+	}
+}
+
+fn is_writable(descriptor: &PropertyDescriptor) -> bool {
+	matches!(
+		descriptor.scriptability,
+		Scriptability::ReadWrite | Scriptability::Write
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn compare_against_defaults() {
+		let mut defaults = DefaultProperties::new(rbx_reflection_database::get_bundled());
+
+		assert!(defaults.is_default("Part", "Anchored", &Variant::Bool(false)));
+		assert!(!defaults.is_default("Part", "Anchored", &Variant::Bool(true)));
+		assert!(!defaults.is_default("NotAClass", "Anchored", &Variant::Bool(false)));
+		assert!(!defaults.is_default("Script", "Source", &Variant::String(String::new())));
+
+		// inherited from BasePart
+		defaults.set_default("BasePart", "Anchored", Variant::Bool(true));
+		assert!(defaults.is_default("Part", "Anchored", &Variant::Bool(true)));
+
+		defaults.keep_property("Instance", "Archivable");
+		assert!(!defaults.is_default("Part", "Archivable", &Variant::Bool(true)));
+	}
+
+	#[test]
+	fn classify_scriptability() {
+		let scriptable = ScriptableProperties::new(rbx_reflection_database::get_bundled());
+
+		assert_eq!(
+			scriptable.access("Part", "Anchored", &Variant::Bool(true)),
+			PropertyAccess::Writable
+		);
+		assert_eq!(
+			scriptable.access(
+				"Part",
+				"Capabilities",
+				&Variant::SecurityCapabilities(Default::default())
+			),
+			PropertyAccess::Unwritable
+		);
+		assert_eq!(
+			scriptable.access("Part", "NotAProperty", &Variant::Bool(true)),
+			PropertyAccess::Unwritable
+		);
+		assert_eq!(
+			scriptable.access("NotAClass", "NotAProperty", &Variant::Bool(true)),
+			PropertyAccess::Writable
+		);
+		assert_eq!(
+			scriptable.access("Script", "Source", &Variant::String(String::new())),
+			PropertyAccess::Writable
+		);
+		assert_eq!(
+			scriptable.access("Fire", "heat_xml", &Variant::Float32(9.0)),
+			PropertyAccess::AliasOf("Heat")
+		);
+		assert_eq!(
+			scriptable.access(
+				"Part",
+				"AttributesSerialize",
+				&Variant::BinaryString(Vec::new().into())
+			),
+			PropertyAccess::Unwritable
+		);
+	}
+
+	#[test]
+	fn drop_default_properties() {
+		let dom =
+			rbx_binary::from_reader(std::fs::File::open("encoding/testRbxms/exampleTrail.rbxm").unwrap())
+				.unwrap();
+		let defaults = DefaultProperties::new(rbx_reflection_database::get_bundled());
+
+		let mut full_payload = Vec::new();
+		crate::encoder::encode_dom_into_writer(
+			&dom,
+			&mut full_payload,
+			crate::emit::Requirements::empty(),
+		)
+		.unwrap();
+
+		let mut payload = Vec::new();
+		crate::encoder::encode_dom_into_writer_with_defaults(
+			&dom,
+			&mut payload,
+			crate::emit::Requirements::empty(),
+			&defaults,
+		)
+		.unwrap();
+		assert!(payload.len() < full_payload.len());
+
+		let full = crate::decoder::decode_dom(&full_payload).unwrap();
+		let decoded = crate::decoder::decode_dom(&payload).unwrap();
+		let original = dom.get_by_ref(dom.root().children()[0]).unwrap();
+		let full = full.get_by_ref(full.root().children()[0]).unwrap();
+		let decoded = decoded.get_by_ref(decoded.root().children()[0]).unwrap();
+
+		assert!(decoded.properties.len() < full.properties.len());
+		// the original values are checked, since decoding can change the variant of some properties (like Int64)
+		for (property, value) in &full.properties {
+			match decoded.properties.get(property) {
+				Some(decoded_value) => assert_eq!(decoded_value, value),
+				None => assert!(
+					defaults.is_default(&original.class, property, &original.properties[property]),
+					"{property} was dropped without being default"
+				),
+			}
+		}
+	}
+
+	#[test]
+	fn skip_unwritable_properties() {
+		use crate::{emit::Requirements, encoder::UnwritableProperty};
+		use rbx_dom_weak::{InstanceBuilder, WeakDom, types::SecurityCapabilities, ustr};
+
+		let mut dom = WeakDom::new(InstanceBuilder::new("DataModel"));
+		let model = dom.insert(dom.root_ref(), InstanceBuilder::new("Model"));
+		dom.insert(
+			model,
+			InstanceBuilder::new("Fire")
+				.with_property("heat_xml", 5.0f32)
+				.with_property("Capabilities", SecurityCapabilities::default()),
+		);
+		dom.insert(
+			model,
+			InstanceBuilder::new("Fire")
+				.with_property("heat_xml", 5.0f32)
+				.with_property("Heat", 7.0f32),
+		);
+
+		let mut payload = Vec::new();
+		let options = crate::encoder::encode_dom_into_writer(
+			&dom,
+			&mut payload,
+			Requirements::SKIP_UNWRITABLE_PROPERTIES | Requirements::COLUMNAR_LAYOUT,
+		)
+		.unwrap();
+
+		assert_eq!(
+			options.unwritable_properties,
+			[
+				UnwritableProperty {
+					class: ustr("Fire"),
+					property: ustr("Capabilities"),
+					encoded_as: None,
+					instance_count: 1,
+				},
+				UnwritableProperty {
+					class: ustr("Fire"),
+					property: ustr("heat_xml"),
+					encoded_as: Some(ustr("Heat")),
+					instance_count: 2,
+				},
+			]
+		);
+
+		let decoded = crate::decoder::decode_dom(&payload).unwrap();
+		let model = decoded.get_by_ref(decoded.root().children()[0]).unwrap();
+		let heats = model
+			.children()
+			.iter()
+			.map(|&fire| {
+				let fire = decoded.get_by_ref(fire).unwrap();
+				assert_eq!(fire.properties.len(), 1);
+				fire.properties[&ustr("Heat")].clone()
+			})
+			.collect::<Vec<_>>();
+
+		assert_eq!(heats, [Variant::Float32(5.0), Variant::Float32(7.0)]);
+	}
+}
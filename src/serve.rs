@@ -0,0 +1,409 @@
+//! Azalea's live sync server
+//!
+//! The server watches model files, and serves them (and patches between their versions) over HTTP to the client script
+//! generated by [`crate::emit::generate_live_sync_client`], which is also served at `/client.luau`.
+//!
+//! Clients poll `/models/{index}?from={version}` for every model (indices start at 1, versions start at 1 and 0 means none).
+//! Responses start with `{version} {kind}\n`, where the kind is one of:
+//! - `current`: the client is up to date, and nothing follows
+//! - `patch`: a patch (see [`crate::diff`]) from the client's version follows
+//! - `payload`: a full payload follows, which replaces the client's tree (used when the client has no version yet, its
+//!   version is older than the last [`KEPT_VERSIONS`] versions, or the model can't be diffed)
+//!
+//! Patches and payloads are zstd compressed and base123 encoded, like the payloads embedded in generated scripts.
+
+use crate::{
+	diff::{diff_doms_into_writer, model_root},
+	emit::{Compression, Requirements, ZstdParameters, compress_payload, generate_live_sync_client},
+	encoder::encode_dom_into_writer,
+};
+use color_eyre::eyre::{self, WrapErr, eyre};
+use rbx_dom_weak::WeakDom;
+use std::{
+	collections::{HashMap, VecDeque},
+	net::TcpListener,
+	path::{Path, PathBuf},
+	sync::Mutex,
+	time::SystemTime,
+};
+
+/// How many versions of a model clients can be patched from, clients with older versions get the full payload.
+pub const KEPT_VERSIONS: usize = 16;
+
+struct WatchedModel {
+	path: PathBuf,
+	/// Modification time and length of the file when it was last loaded
+	stamp: Option<(SystemTime, u64)>,
+	/// The latest version of the model, version 1 being the first
+	version: usize,
+	/// The last [`KEPT_VERSIONS`] loaded versions of the model, the latest one being last
+	versions: VecDeque<WeakDom>,
+	/// The compressed and base123 encoded payload of the latest version
+	payload: String,
+	/// Encoded patches from kept versions to the latest version (or `None` if it couldn't be diffed), by the version they
+	/// patch from
+	patches: HashMap<usize, Option<String>>,
+}
+
+/// A live sync server for a set of model files.
+pub struct Server {
+	models: Mutex<Vec<WatchedModel>>,
+	load_model: fn(&Path) -> eyre::Result<WeakDom>,
+	requirements: Requirements,
+	level: u8,
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+	let metadata = std::fs::metadata(path).ok()?;
+	Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Compresses and base123 encodes a payload or patch, see the module documentation.
+fn encode_body(data: Vec<u8>, level: u8) -> String {
+	let encoded = crate::base122::base123_encode(&compress_payload(
+		data,
+		Compression::Zstd(ZstdParameters::level(level)),
+	));
+
+	String::from_utf8(encoded).expect("Base123 output is UTF-8")
+}
+
+impl Server {
+	/// Loads every model in `paths` with `load_model`. Payloads are encoded with `requirements`, and compressed with the
+	/// zstd compression `level`.
+	///
+	/// # Errors
+	///
+	/// Errors if a model can't be loaded or encoded, or doesn't have exactly one top-level instance.
+	pub fn new(
+		paths: Vec<PathBuf>,
+		load_model: fn(&Path) -> eyre::Result<WeakDom>,
+		requirements: Requirements,
+		level: u8,
+	) -> eyre::Result<Self> {
+		let server = Self {
+			models: Mutex::new(Vec::with_capacity(paths.len())),
+			load_model,
+			requirements,
+			level,
+		};
+
+		for path in paths {
+			let stamp = file_stamp(&path);
+			let (weak_dom, payload) = server
+				.load(&path)
+				.wrap_err_with(|| format!("failed loading {}", path.display()))?;
+
+			server.models.lock().unwrap().push(WatchedModel {
+				path,
+				stamp,
+				version: 1,
+				versions: VecDeque::from([weak_dom]),
+				payload,
+				patches: HashMap::new(),
+			});
+		}
+
+		Ok(server)
+	}
+
+	fn load(&self, path: &Path) -> eyre::Result<(WeakDom, String)> {
+		let weak_dom = (self.load_model)(path)?;
+		model_root(&weak_dom)?;
+
+		let mut payload = Vec::new();
+		encode_dom_into_writer(&weak_dom, &mut payload, self.requirements)
+			.wrap_err("failed encoding model")?;
+		let payload = encode_body(payload, self.level);
+
+		Ok((weak_dom, payload))
+	}
+
+	/// Returns the file names of the served models, in the order they are served in.
+	#[must_use]
+	pub fn model_names(&self) -> Vec<String> {
+		self
+			.models
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|model| {
+				model.path.file_name().map_or_else(
+					|| model.path.display().to_string(),
+					|name| name.to_string_lossy().into_owned(),
+				)
+			})
+			.collect()
+	}
+
+	/// Reloads every model whose file changed since it was last loaded.
+	/// Returns the path of every changed model, with its new version or why it couldn't be reloaded (it keeps its last version).
+	pub fn refresh(&self) -> Vec<(PathBuf, eyre::Result<usize>)> {
+		let mut refreshed = Vec::new();
+
+		for model in self.models.lock().unwrap().iter_mut() {
+			let stamp = file_stamp(&model.path);
+			if stamp.is_none() || stamp == model.stamp {
+				continue;
+			}
+
+			// a file which is still being written is retried once it changes again
+			model.stamp = stamp;
+
+			let result = self.load(&model.path).map(|(weak_dom, payload)| {
+				if model.versions.len() == KEPT_VERSIONS {
+					model.versions.pop_front();
+				}
+
+				model.versions.push_back(weak_dom);
+				model.version += 1;
+				model.payload = payload;
+				model.patches.clear();
+				model.version
+			});
+
+			refreshed.push((model.path.clone(), result));
+		}
+
+		refreshed
+	}
+
+	/// Returns the response body for a `/models/{index}?from={version}` request, or `None` if `url` doesn't name a model.
+	#[must_use]
+	pub fn respond(&self, url: &str) -> Option<String> {
+		let (path, query) = url.split_once('?').unwrap_or((url, ""));
+		let index = path.strip_prefix("/models/")?.parse::<usize>().ok()?;
+		let from = match query.split('&').find_map(|pair| pair.strip_prefix("from=")) {
+			Some(from) => from.parse::<usize>().ok()?,
+			None => 0,
+		};
+
+		let mut models = self.models.lock().unwrap();
+		let model = models.get_mut(index.checked_sub(1)?)?;
+		let version = model.version;
+
+		if from == version {
+			return Some(format!("{version} current\n"));
+		}
+
+		// versions[0] is the oldest kept version
+		let oldest = version + 1 - model.versions.len();
+		if let Some(old) = from
+			.checked_sub(oldest)
+			.and_then(|index| model.versions.get(index))
+		{
+			let latest = model.versions.back().unwrap();
+			let patch = model.patches.entry(from).or_insert_with(|| {
+				let mut patch = Vec::new();
+				diff_doms_into_writer(old, latest, &mut patch, Requirements::empty())
+					.ok()
+					.map(|_| encode_body(patch, self.level))
+			});
+
+			if let Some(patch) = patch {
+				return Some(format!("{version} patch\n{patch}"));
+			}
+		}
+
+		Some(format!("{version} payload\n{}", model.payload))
+	}
+
+	/// Serves the models (and the client script) on `listener` until it fails.
+	///
+	/// # Errors
+	///
+	/// Errors if the HTTP server can't be started.
+	pub fn handle_requests(&self, listener: TcpListener) -> eyre::Result<()> {
+		let client = generate_live_sync_client(
+			&format!("http://{}", listener.local_addr()?),
+			&self.model_names(),
+		);
+
+		let http_server = tiny_http::Server::from_listener(listener, None)
+			.map_err(|error| eyre!("failed starting http server: {error}"))?;
+
+		for request in http_server.incoming_requests() {
+			let body = if request.url() == "/client.luau" {
+				Some(client.clone())
+			} else {
+				self.respond(request.url())
+			};
+
+			let response = match body {
+				Some(body) => tiny_http::Response::from_string(body),
+				None => tiny_http::Response::from_string("not found").with_status_code(404),
+			};
+
+			// the client going away shouldn't stop the server
+			let _ = request.respond(response);
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::decoder::{apply_patch, decode_dom};
+	use rbx_dom_weak::{InstanceBuilder, types::Variant};
+	use std::{
+		io::{Read, Write},
+		net::TcpStream,
+		sync::Arc,
+		time::Duration,
+	};
+
+	fn load_rbxm(path: &Path) -> eyre::Result<WeakDom> {
+		Ok(rbx_binary::from_reader(std::fs::File::open(path)?)?)
+	}
+
+	fn write_rbxm(path: &Path, value: &str, modified: SystemTime) {
+		let mut weak_dom = WeakDom::new(InstanceBuilder::new("DataModel"));
+		let root = weak_dom.insert(
+			weak_dom.root_ref(),
+			InstanceBuilder::new("Folder").with_name("Root"),
+		);
+		weak_dom.insert(
+			root,
+			InstanceBuilder::new("StringValue")
+				.with_name("Value")
+				.with_property("Value", value),
+		);
+
+		let file = std::fs::File::create(path).unwrap();
+		rbx_binary::to_writer(&file, &weak_dom, &[root]).unwrap();
+		file.set_modified(modified).unwrap();
+	}
+
+	fn get(address: std::net::SocketAddr, url: &str) -> (String, String) {
+		let mut stream = TcpStream::connect(address).unwrap();
+		write!(
+			stream,
+			"GET {url} HTTP/1.1\r\nHost: {address}\r\nConnection: close\r\n\r\n"
+		)
+		.unwrap();
+
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+
+		let (head, body) = response.split_once("\r\n\r\n").unwrap();
+		(head.lines().next().unwrap().to_owned(), body.to_owned())
+	}
+
+	fn decode_body(body: &str, expected_kind: &str) -> (usize, Vec<u8>) {
+		let (line, data) = body.split_once('\n').unwrap();
+		let (version, kind) = line.split_once(' ').unwrap();
+		assert_eq!(kind, expected_kind);
+
+		let compressed = crate::base122::base123_decode(data.as_bytes()).unwrap();
+		(
+			version.parse().unwrap(),
+			zstd::decode_all(compressed.as_slice()).unwrap(),
+		)
+	}
+
+	fn string_value(weak_dom: &WeakDom) -> Variant {
+		let root = weak_dom.get_by_ref(model_root(weak_dom).unwrap()).unwrap();
+		let value = weak_dom.get_by_ref(root.children()[0]).unwrap();
+		value.properties[&"Value".into()].clone()
+	}
+
+	#[test]
+	fn serves_payloads_and_patches() {
+		let directory = std::env::temp_dir().join(format!("azalea-serve-{}", std::process::id()));
+		std::fs::create_dir_all(&directory).unwrap();
+		let path = directory.join("model.rbxm");
+		let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+		write_rbxm(&path, "first", start);
+		let server =
+			Arc::new(Server::new(vec![path.clone()], load_rbxm, Requirements::empty(), 3).unwrap());
+		assert_eq!(server.model_names(), ["model.rbxm"]);
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		std::thread::spawn({
+			let server = Arc::clone(&server);
+			move || server.handle_requests(listener)
+		});
+
+		let (status, body) = get(address, "/models/1");
+		assert!(status.contains("200"), "{status}");
+		let (version, payload) = decode_body(&body, "payload");
+		assert_eq!(version, 1);
+		let decoded = decode_dom(&payload).unwrap();
+		assert_eq!(string_value(&decoded), Variant::String("first".into()));
+
+		assert_eq!(get(address, "/models/1?from=1").1, "1 current\n");
+		assert!(server.refresh().is_empty());
+
+		write_rbxm(&path, "second", start + Duration::from_secs(1));
+		let refreshed = server.refresh();
+		assert_eq!(refreshed.len(), 1);
+		assert_eq!(*refreshed[0].1.as_ref().unwrap(), 2);
+
+		let (version, patch) = decode_body(&get(address, "/models/1?from=1").1, "patch");
+		assert_eq!(version, 2);
+		let patched = apply_patch(decoded, &patch).unwrap();
+		assert_eq!(string_value(&patched), Variant::String("second".into()));
+
+		// unknown versions get the full payload
+		assert_eq!(
+			decode_body(&get(address, "/models/1?from=7").1, "payload").0,
+			2
+		);
+
+		let (_, client) = get(address, "/client.luau");
+		assert!(client.contains(&format!("local SERVER_URL = \"http://{address}\"")));
+		assert!(client.contains("local MODEL_NAMES = { \"model.rbxm\" }"));
+
+		assert!(get(address, "/models/2").0.contains("404"));
+		assert!(get(address, "/models/1?from=x").0.contains("404"));
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+
+	#[test]
+	fn keeps_a_window_of_versions() {
+		let directory = std::env::temp_dir().join(format!("azalea-versions-{}", std::process::id()));
+		std::fs::create_dir_all(&directory).unwrap();
+		let path = directory.join("model.rbxm");
+		let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+		write_rbxm(&path, "1", start);
+		let server = Server::new(vec![path.clone()], load_rbxm, Requirements::empty(), 3).unwrap();
+
+		let latest = KEPT_VERSIONS + 4;
+		for version in 2..=latest {
+			write_rbxm(
+				&path,
+				&version.to_string(),
+				start + Duration::from_secs(version as u64),
+			);
+			assert_eq!(*server.refresh()[0].1.as_ref().unwrap(), version);
+		}
+
+		let models = server.models.lock().unwrap();
+		assert_eq!(models[0].versions.len(), KEPT_VERSIONS);
+		drop(models);
+
+		// versions which are no longer kept get the full payload
+		let oldest = latest + 1 - KEPT_VERSIONS;
+		for from in [1, oldest - 1] {
+			let body = server.respond(&format!("/models/1?from={from}")).unwrap();
+			assert_eq!(decode_body(&body, "payload").0, latest);
+		}
+
+		// patches are only diffed once per version they patch from
+		let patch = server.respond(&format!("/models/1?from={oldest}")).unwrap();
+		assert_eq!(decode_body(&patch, "patch").0, latest);
+		assert_eq!(
+			server.respond(&format!("/models/1?from={oldest}")).unwrap(),
+			patch
+		);
+		assert_eq!(server.models.lock().unwrap()[0].patches.len(), 1);
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+}
@@ -1,8 +1,77 @@
-//! Azalea's type id and variant decoder generator
+//! Azalea's format header, type id and variant decoder generator
 
+use color_eyre::eyre;
 use rbx_dom_weak::types::Variant;
 use std::fmt::Write;
 
+/// Every payload starts with these bytes, followed by a [`FORMAT_VERSION`] byte and [`FormatFlags`] (u16, little endian).
+///
+/// The header is followed by the string table (a LEB128 encoded count of varstrings), which interned strings index into.
+pub const FORMAT_MAGIC: [u8; 6] = *b"AZALEA";
+
+/// The version of the payload layout. Bump this whenever a change makes old decoders misread new payloads.
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Patches (see [`crate::diff`]) start with these bytes instead of [`FORMAT_MAGIC`], so they can't be mistaken for a full payload.
+///
+/// The rest of the header and the string table are laid out like in full payloads, followed by [`PatchRecordKind`] prefixed records.
+pub const PATCH_MAGIC: [u8; 6] = *b"AZPTCH";
+
+/// Length of the header (magic, version and flags) at the start of every payload.
+pub const HEADER_LENGTH: usize = FORMAT_MAGIC.len() + 1 + 2;
+
+bitflags::bitflags! {
+	/// Features a payload was encoded with. Decoders must refuse payloads with flags they do not support.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+	pub struct FormatFlags: u16 {
+		/// ModuleScript sources were inlined into the generated script, see [`crate::emit::Requirements::USE_NOVEL_INLINING`].
+		/// Their Source property is encoded as [`TypeId::None`].
+		const NOVEL_INLINING = 1;
+		/// Instances reference a schema (their ClassName and property names) from the schema table which follows the string table,
+		/// and only encode their property values in schema order, see [`crate::emit::Requirements::PROPERTY_SCHEMAS`].
+		const PROPERTY_SCHEMAS = 2;
+		/// Instance records are followed by one column per schema property, which holds that property's value for every
+		/// instance of the schema, see [`crate::emit::Requirements::COLUMNAR_LAYOUT`]. Always set alongside [`Self::PROPERTY_SCHEMAS`].
+		const COLUMNAR_LAYOUT = 4;
+		/// Every instance record starts with a [`RecordKind`], and repeated subtrees are encoded as [`RecordKind::Clone`] records,
+		/// see [`crate::emit::Requirements::DEDUPLICATE_SUBTREES`].
+		const CLONED_SUBTREES = 8;
+		/// The string table is followed by a blob table (a LEB128 encoded count of varstrings), which [`TypeId::StringBlob`] and
+		/// [`TypeId::BinaryStringBlob`] values index into, see [`crate::emit::Requirements::BLOB_TABLE`].
+		const BLOB_TABLE = 16;
+	}
+}
+
+impl FormatFlags {
+	/// Derives the flags a payload encoded with `requirements` uses.
+	#[must_use]
+	pub fn from_requirements(requirements: crate::emit::Requirements) -> Self {
+		let mut flags = Self::empty();
+
+		if requirements.contains(crate::emit::Requirements::USE_NOVEL_INLINING) {
+			flags |= Self::NOVEL_INLINING;
+		}
+
+		if requirements.contains(crate::emit::Requirements::PROPERTY_SCHEMAS) {
+			flags |= Self::PROPERTY_SCHEMAS;
+		}
+
+		if requirements.contains(crate::emit::Requirements::COLUMNAR_LAYOUT) {
+			flags |= Self::PROPERTY_SCHEMAS | Self::COLUMNAR_LAYOUT;
+		}
+
+		if requirements.contains(crate::emit::Requirements::DEDUPLICATE_SUBTREES) {
+			flags |= Self::CLONED_SUBTREES;
+		}
+
+		if requirements.contains(crate::emit::Requirements::BLOB_TABLE) {
+			flags |= Self::BLOB_TABLE;
+		}
+
+		flags
+	}
+}
+
 // <https://veykril.github.io/tlborm/decl-macros/building-blocks/counting.html#bit-twiddling>
 macro_rules! count_tt {
 	() => { 0 };
@@ -13,11 +82,22 @@ macro_rules! count_tt {
 macro_rules! define_type_id {
 	($($name:ident = $value:expr,)+) => {
 		#[repr(u8)]
-		#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+		#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 		pub enum TypeId {
 			$($name = ($value as u8)),*
 		}
 
+		impl TryFrom<u8> for TypeId {
+			type Error = eyre::Report;
+
+			fn try_from(value: u8) -> Result<Self, Self::Error> {
+				match value {
+					$(value if value == ($value as u8) => Ok(TypeId::$name),)*
+					_ => Err(eyre::eyre!("no variant decoder for type id {value}")),
+				}
+			}
+		}
+
 		pub const ALL_TYPE_IDS: [TypeId; count_tt!($($name)*)] = [
 			$(TypeId::$name),*
 		];
@@ -104,6 +184,133 @@ define_type_id! {
 	ContentNone = 35,
 	ContentObject = 36,
 	ContentUri = 37,
+
+	StringBlob = 38,
+	BinaryStringBlob = 39,
+}
+
+impl TypeId {
+	/// Returns how many 4 byte little endian words (f32, i32) make up this type, if it is made of nothing else.
+	///
+	/// Columns of these types are transposed (and optionally byte shuffled) in [`FormatFlags::COLUMNAR_LAYOUT`] payloads.
+	#[must_use]
+	pub const fn word_count(self) -> Option<usize> {
+		Some(match self {
+			Self::Float32 | Self::Int32 => 1,
+			Self::NumberRange | Self::UDim | Self::Vector2 => 2,
+			Self::Color3 | Self::Vector3 => 3,
+			Self::Rect | Self::UDim2 => 4,
+			Self::CustomPhysicalProperties => 5,
+			Self::Ray | Self::Region3 => 6,
+			_ => return None,
+		})
+	}
+}
+
+/// Generates a Luau table which maps type ids to their [`TypeId::word_count`].
+#[must_use]
+pub fn get_luau_column_word_counts() -> String {
+	let mut output =
+		String::from("local COLUMN_WORD_COUNTS: { [number]: number } = table.freeze({\n");
+
+	for id in ALL_TYPE_IDS {
+		if let Some(word_count) = id.word_count() {
+			writeln!(
+				&mut output,
+				"[{}] = {word_count}, -- {}",
+				id as u8,
+				type_id_to_name(&id)
+			)
+			.unwrap();
+		}
+	}
+
+	output.push_str("})");
+	output
+}
+
+/// How the values of a [`FormatFlags::COLUMNAR_LAYOUT`] column are laid out, written as a u8 before each column.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColumnKind {
+	/// One variant (type id and value) per instance, exactly like row payloads.
+	Variants = 0,
+	/// Every value has the same [`TypeId`] (written once), and its words are transposed: the first word of every value,
+	/// then the second word of every value, and so on.
+	Words = 1,
+	/// Like [`Self::Words`], but the bytes are also shuffled: the first byte of every word, then the second byte of every word...
+	ShuffledWords = 2,
+}
+
+impl TryFrom<u8> for ColumnKind {
+	type Error = eyre::Report;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		Ok(match value {
+			0 => Self::Variants,
+			1 => Self::Words,
+			2 => Self::ShuffledWords,
+			_ => eyre::bail!("unknown column kind {value}"),
+		})
+	}
+}
+
+/// What an instance record of a [`FormatFlags::CLONED_SUBTREES`] payload holds, written as a u8 before each record.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordKind {
+	/// A regular instance record.
+	Instance = 0,
+	/// A copy of an earlier subtree: its name, the referent of the subtree to `:Clone()`, the instance referent, the parent referent,
+	/// and a LEB128 encoded count of referents for the descendants of the clone (in `GetDescendants` order).
+	Clone = 1,
+}
+
+impl TryFrom<u8> for RecordKind {
+	type Error = eyre::Report;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		Ok(match value {
+			0 => Self::Instance,
+			1 => Self::Clone,
+			_ => eyre::bail!("unknown record kind {value}"),
+		})
+	}
+}
+
+/// What a record of a patch holds, written as a u8 before each record.
+///
+/// Instances which exist before the patch is applied are addressed by their path below the patched root, every other record
+/// addresses instances by their referent. Every [`Self::Locate`] record comes first, as later records change paths.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PatchRecordKind {
+	/// A referent, and the path of the instance it stands for: a LEB128 encoded count of segments, each one an interned name
+	/// and the LEB128 encoded index of the instance among its siblings with that name.
+	Locate = 0,
+	/// A new instance, laid out like an instance record of a full payload (without any [`FormatFlags`]).
+	Add = 1,
+	/// A referent, followed by a u16 count of interned property names and their new values, like the properties of an instance record.
+	Change = 2,
+	/// A referent, and the referent of its new parent.
+	Reparent = 3,
+	/// A referent of an instance to destroy.
+	Remove = 4,
+}
+
+impl TryFrom<u8> for PatchRecordKind {
+	type Error = eyre::Report;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		Ok(match value {
+			0 => Self::Locate,
+			1 => Self::Add,
+			2 => Self::Change,
+			3 => Self::Reparent,
+			4 => Self::Remove,
+			_ => eyre::bail!("unknown patch record kind {value}"),
+		})
+	}
 }
 
 #[must_use]
@@ -179,6 +386,12 @@ decode_type_id! {
 		loc += stringLength
 		return buffer.readstring(payloadBuffer, loc - stringLength, stringLength)
 	"#,
+	TypeId::StringBlob => r#"
+		return nextBlob()
+	"#,
+	TypeId::BinaryStringBlob => r#"
+		return nextBlob()
+	"#,
 	TypeId::None => r#"
 		return nil
 	"#,
@@ -214,7 +427,7 @@ decode_type_id! {
 		local tags = {}
 
 		while tagsLength > 0 do
-			local tag = nextNullstring()
+			local tag = nextInternedString()
 			table.insert(tags, tag)
 
 			tagsLength -= 1
@@ -227,7 +440,7 @@ decode_type_id! {
 		local attributeMap: { [string]: any } = {}
 
 		while attributesLength > 0 do
-			local attributeName = nextNullstring()
+			local attributeName = nextInternedString()
 			attributeMap[attributeName] = nextVariant()
 			-- print(attributeName, #attributeName, attributeMap[attributeName])
 
@@ -241,7 +454,7 @@ decode_type_id! {
 		loc += 8
 	"#,
 	TypeId::BrickColor => r#"
-		return BrickColor.new(nextNullstring() :: any)
+		return BrickColor.new(nextInternedString() :: any)
 	"#,
 	TypeId::UDim => r#"
 		local offset, scale = buffer.readi32(payloadBuffer, loc), buffer.readf32(payloadBuffer, loc + 4)